@@ -0,0 +1,345 @@
+//! Terminal debugger.
+//!
+//! Usage: `gbdb <rom> [sym]`, where `sym` is an RGBDS symbol file. Type `help` at the prompt
+//! for the list of commands.
+use emulator::{
+    cartridge,
+    cpu::cpu::{profile::Profiler, trace::Tracer, Access},
+    debugger::debugger::{parse_number, Breakpoint, Debugger, Event, Stop},
+    device::device::Device,
+    disasm::symbols::Symbols,
+    mmu::mmu::cdl::Cdl,
+    Builder,
+};
+use std::{
+    env, fs,
+    io::{self, BufRead, Write},
+    process,
+};
+
+const HELP: &str = "\
+s, step [n]                 execute n instructions (default 1), entering calls
+n, next                     execute one instruction, running calls until they return
+finish                      run until the current function returns
+c, continue                 run until a breakpoint, watchpoint or condition triggers
+vblank                      run until the next VBlank
+int                         run until the next interrupt is dispatched
+b, break loc [if cond]      break at loc (e.g. `b 01:4000 if a==$3f` or `b Main`)
+cond <cond>                 break as soon as cond holds (e.g. `cond hl>=$c000`)
+w, watch loc [r|w|rw]       break on reads and/or writes of loc (default w)
+d, delete loc               delete the breakpoints and watchpoints at loc
+uncond <cond>               delete a condition
+i, info                     list breakpoints, watchpoints and conditions
+r, regs                     show registers
+x loc [len]                 dump memory
+dis [loc] [n]               disassemble n instructions (default 10) at loc (default PC)
+sym <file>                  load an RGBDS symbol file
+trace <file> [lo-hi] [bank] log executed instructions in Gameboy Doctor format
+trace on|off                resume or pause the trace log
+cdl start                   start (or restart) the code/data log
+cdl save <file>             save the ROM code/data log in .cdl format
+cdl stats                   show how much of the ROM was executed or read
+prof start                  start (or restart) the profiler
+prof report [n]             show the n hottest locations and call targets (default 20)
+prof stop                   stop the profiler
+q, quit                     exit
+Locations are a label, `bank:addr` or `addr`. Addresses and values are in hex.
+An empty line repeats the last command.";
+
+// Instructions after which `vblank` and `int` give up (a few seconds of emulated time).
+const EVENT_STEPS: u64 = 10_000_000;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("usage: {} <rom> [sym]", args[0]);
+        process::exit(1);
+    }
+    let rom = fs::read(&args[1]).unwrap_or_else(|e| {
+        eprintln!("error: failed to read {}: {}", args[1], e);
+        process::exit(1);
+    });
+    let cartridge = cartridge::from_bytes(&rom).unwrap_or_else(|_| {
+        eprintln!("error: unsupported cartridge type");
+        process::exit(1);
+    });
+
+    let gb = Builder::default().cartridge(cartridge).skip_boot().build();
+    let mut dbg = Debugger::new(gb);
+    if let Some(path) = args.get(2) {
+        if let Err(e) = command(&mut dbg, &format!("sym {}", path)) {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    }
+    print_location(&dbg);
+
+    let stdin = io::stdin();
+    let mut last = String::new();
+    loop {
+        print!("(gbdb) ");
+        io::stdout().flush().unwrap();
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+        let line = match line.trim() {
+            "" => last.clone(),
+            line => line.to_string(),
+        };
+        if line.is_empty() {
+            continue;
+        }
+        last = line.clone();
+
+        match command(&mut dbg, &line) {
+            Ok(true) => break,
+            Ok(false) => {}
+            Err(e) => println!("error: {}", e),
+        }
+    }
+}
+
+type Dbg = Debugger<Box<dyn cartridge::cartridge::Cartridge>, (), ()>;
+
+// Runs a command. Returns true if the debugger should exit.
+fn command(dbg: &mut Dbg, line: &str) -> Result<bool, String> {
+    let mut args = line.split_whitespace();
+    let cmd = args.next().unwrap_or_default();
+    let args: Vec<&str> = args.collect();
+    let location = |i: usize| -> Result<Option<(Option<usize>, u16)>, String> {
+        args.get(i)
+            .map(|s| dbg.parse_location(s).ok_or_else(|| format!("invalid location `{}`", s)))
+            .transpose()
+    };
+    let count = |i: usize, default: u64| -> Result<u64, String> {
+        args.get(i)
+            .map_or(Ok(default), |s| s.parse().map_err(|_| format!("invalid count `{}`", s)))
+    };
+
+    match cmd {
+        "s" | "step" => {
+            let n = count(0, 1)?;
+            let mut stop = Stop::Step;
+            for _ in 0..n {
+                stop = dbg.step_into();
+                if stop != Stop::Step {
+                    break;
+                }
+            }
+            report(dbg, stop);
+        }
+        "n" | "next" => {
+            let stop = dbg.step_over();
+            report(dbg, stop)
+        }
+        "finish" => {
+            let stop = dbg.step_out();
+            report(dbg, stop)
+        }
+        "c" | "continue" => {
+            let stop = dbg.cont();
+            report(dbg, stop)
+        }
+        "vblank" => {
+            let stop = dbg.run_until_event(Event::VBlank, EVENT_STEPS);
+            report_event(dbg, stop)
+        }
+        "int" => {
+            let stop = dbg.run_until_event(Event::Interrupt, EVENT_STEPS);
+            report_event(dbg, stop)
+        }
+        "b" | "break" => {
+            let (bank, addr) = location(0)?.ok_or("missing location")?;
+            let cond = match args.get(1..) {
+                Some(["if", cond @ ..]) if !cond.is_empty() => {
+                    Some(cond.concat().parse().map_err(|_| "invalid condition")?)
+                }
+                Some([]) | None => None,
+                _ => return Err("expected `if <cond>`".into()),
+            };
+            let bp = Breakpoint { bank, addr, cond };
+            println!("breakpoint {}", bp);
+            dbg.add_breakpoint(bp);
+        }
+        "cond" | "uncond" => {
+            let cond = args.concat().parse().map_err(|_| "invalid condition")?;
+            if cmd == "cond" {
+                dbg.add_condition(cond);
+            } else {
+                dbg.remove_condition(cond);
+            }
+        }
+        "w" | "watch" => {
+            let (_, addr) = location(0)?.ok_or("missing location")?;
+            let access: &[Access] = match args.get(1).copied().unwrap_or("w") {
+                "r" => &[Access::Read],
+                "w" => &[Access::Write],
+                "rw" => &[Access::Read, Access::Write],
+                _ => return Err("expected r, w or rw".into()),
+            };
+            for &access in access {
+                dbg.add_watchpoint(addr, access);
+            }
+        }
+        "d" | "delete" => {
+            let (_, addr) = location(0)?.ok_or("missing location")?;
+            dbg.remove_breakpoint(addr);
+            dbg.remove_watchpoint(addr, Access::Read);
+            dbg.remove_watchpoint(addr, Access::Write);
+        }
+        "i" | "info" => {
+            for bp in dbg.breakpoints() {
+                println!("breakpoint {}", bp);
+            }
+            for (addr, access) in dbg.gb().cpu().watchpoints() {
+                println!("watchpoint {:04X} {:?}", addr, access);
+            }
+            for cond in dbg.conditions() {
+                println!("condition {}", cond);
+            }
+        }
+        "r" | "regs" => print_regs(dbg),
+        "x" => {
+            let (_, addr) = location(0)?.ok_or("missing location")?;
+            let len = count(1, 16)? as u16;
+            let mmu = dbg.gb().mmu();
+            for row in (0..len).step_by(16) {
+                let start = addr.wrapping_add(row);
+                let bytes: Vec<String> = (0..16.min(len - row))
+                    .map(|i| format!("{:02X}", mmu.read(start.wrapping_add(i))))
+                    .collect();
+                println!("{:04X}  {}", start, bytes.join(" "));
+            }
+        }
+        "dis" => {
+            let mut addr = location(0)?.map_or(dbg.gb().cpu().reg().pc, |(_, addr)| addr);
+            for _ in 0..count(1, 10)? {
+                print_instr(dbg, addr);
+                addr = dbg.decode(addr).next();
+            }
+        }
+        "sym" => {
+            let path = args.first().ok_or("missing file")?;
+            let symbols = Symbols::load(path).map_err(|e| e.to_string())?;
+            dbg.set_symbols(symbols);
+        }
+        "trace" => match args.first().copied() {
+            Some("on") | Some("off") => {
+                let tracer = dbg.gb_mut().cpu_mut().tracer_mut().ok_or("not tracing")?;
+                if args[0] == "on" {
+                    tracer.start();
+                } else {
+                    tracer.stop();
+                }
+                tracer.flush().map_err(|e| e.to_string())?;
+            }
+            Some(path) => {
+                let file = fs::File::create(path).map_err(|e| e.to_string())?;
+                let mut tracer = Tracer::new(io::BufWriter::new(file));
+                if let Some(range) = args.get(1) {
+                    let (lo, hi) = range.split_once('-').ok_or("expected lo-hi")?;
+                    let lo = parse_number(lo).ok_or("invalid address")?;
+                    let hi = parse_number(hi).ok_or("invalid address")?;
+                    tracer = tracer.pc_range(lo..=hi);
+                }
+                if let Some(bank) = args.get(2) {
+                    tracer = tracer.bank(parse_number(bank).ok_or("invalid bank")? as usize);
+                }
+                if !dbg.symbols().is_empty() {
+                    tracer = tracer.symbols(dbg.symbols().clone());
+                }
+                if let Some(mut old) = dbg.gb_mut().cpu_mut().set_tracer(Some(tracer)) {
+                    old.flush().map_err(|e| e.to_string())?;
+                }
+            }
+            None => return Err("missing file".into()),
+        },
+        "cdl" => match args.first().copied() {
+            Some("start") => {
+                let size = dbg.gb().mmu().cartridge().rom().len();
+                dbg.gb_mut().mmu_mut().set_cdl(Some(Cdl::new(size)));
+            }
+            Some("save") => {
+                let path = args.get(1).ok_or("missing file")?;
+                let cdl = dbg.gb().mmu().cdl().ok_or("not logging")?;
+                let file = fs::File::create(path).map_err(|e| e.to_string())?;
+                cdl.write_cdl(io::BufWriter::new(file)).map_err(|e| e.to_string())?;
+            }
+            Some("stats") => {
+                let cdl = dbg.gb().mmu().cdl().ok_or("not logging")?;
+                let (code, data, untouched) = cdl.rom_stats();
+                let pct = |n: usize| 100.0 * n as f64 / cdl.rom().len().max(1) as f64;
+                println!("code      {:>8} bytes {:5.1}%", code, pct(code));
+                println!("data      {:>8} bytes {:5.1}%", data, pct(data));
+                println!("untouched {:>8} bytes {:5.1}%", untouched, pct(untouched));
+            }
+            _ => return Err("expected start, save or stats".into()),
+        },
+        "prof" => match args.first().copied() {
+            Some("start") => {
+                dbg.gb_mut().cpu_mut().set_profiler(Some(Profiler::new()));
+            }
+            Some("report") => {
+                let n = count(1, 20)? as usize;
+                let profiler = dbg.gb().cpu().profiler().ok_or("not profiling")?;
+                let symbols = Some(dbg.symbols()).filter(|s| !s.is_empty());
+                profiler.report(io::stdout().lock(), n, symbols).map_err(|e| e.to_string())?;
+            }
+            Some("stop") => {
+                dbg.gb_mut().cpu_mut().set_profiler(None);
+            }
+            _ => return Err("expected start, report or stop".into()),
+        },
+        "h" | "help" => println!("{}", HELP),
+        "q" | "quit" => return Ok(true),
+        _ => return Err(format!("unknown command `{}` (try `help`)", cmd)),
+    }
+    Ok(false)
+}
+
+fn report(dbg: &Dbg, stop: Stop) {
+    if stop != Stop::Step {
+        println!("stopped: {}", stop);
+    }
+    print_location(dbg);
+}
+
+fn report_event(dbg: &Dbg, stop: Stop) {
+    if stop == Stop::Step {
+        println!("gave up after {} instructions", EVENT_STEPS);
+    }
+    report(dbg, stop);
+}
+
+fn print_location(dbg: &Dbg) {
+    print_instr(dbg, dbg.gb().cpu().reg().pc);
+}
+
+fn print_instr(dbg: &Dbg, addr: u16) {
+    let instr = dbg.decode(addr);
+    if dbg.symbols().is_empty() {
+        println!("{:02X}:{:04X}  {}", instr.bank, addr, instr);
+    } else {
+        println!("{:02X}:{:04X}  {:<24} {}",
+                 instr.bank,
+                 addr,
+                 format!("<{}>", dbg.label(addr)),
+                 instr.display(dbg.symbols()));
+    }
+}
+
+fn print_regs(dbg: &Dbg) {
+    let cpu = dbg.gb().cpu();
+    let reg = cpu.reg();
+    println!("AF={:04X} BC={:04X} DE={:04X} HL={:04X} SP={:04X} PC={:04X} IME={} {:?}",
+             reg.af(),
+             reg.bc(),
+             reg.de(),
+             reg.hl(),
+             reg.sp,
+             reg.pc,
+             u8::from(cpu.ime()),
+             cpu.state());
+}
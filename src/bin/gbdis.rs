@@ -0,0 +1,69 @@
+//! Disassembles a cartridge ROM bank by bank.
+//!
+//! Usage: `gbdis [--sym <file>] <rom> [bank:addr|label]...`
+//!
+//! Code is found by following the control flow from the cartridge entry point, the RST and
+//! interrupt vectors, and any extra `bank:addr` entry points (in hex) given on the command
+//! line. Everything else is dumped as data.
+//!
+//! With `--sym`, the labels of an RGBDS symbol file replace the generated ones and can be
+//! used as entry points.
+use emulator::disasm::{
+    disasm::{Disassembler, ENTRY_POINTS},
+    symbols::Symbols,
+};
+use std::{
+    env, fs,
+    io::{self, BufWriter},
+    process,
+};
+
+fn parse_entry(arg: &str) -> Option<(usize, u16)> {
+    let (bank, addr) = arg.split_once(':')?;
+    let bank = usize::from_str_radix(bank, 16).ok()?;
+    let addr = u16::from_str_radix(addr, 16).ok()?;
+    Some((bank, addr))
+}
+
+fn main() {
+    let mut args: Vec<String> = env::args().collect();
+    let mut symbols = Symbols::new();
+    if let Some(i) = args.iter().position(|arg| arg == "--sym") {
+        let path = args.get(i + 1).cloned().unwrap_or_default();
+        symbols = Symbols::load(&path).unwrap_or_else(|e| {
+            eprintln!("error: failed to load symbols from {}: {}", path, e);
+            process::exit(1);
+        });
+        args.drain(i..(i + 2).min(args.len()));
+    }
+    if args.len() < 2 {
+        eprintln!("usage: {} [--sym <file>] <rom> [bank:addr|label]...", args[0]);
+        process::exit(1);
+    }
+
+    let rom = fs::read(&args[1]).unwrap_or_else(|e| {
+        eprintln!("error: failed to read {}: {}", args[1], e);
+        process::exit(1);
+    });
+
+    let mut entries: Vec<(usize, u16)> = ENTRY_POINTS.iter().map(|&addr| (0, addr)).collect();
+    for arg in &args[2..] {
+        match symbols.lookup(arg).or_else(|| parse_entry(arg)) {
+            Some(entry) => entries.push(entry),
+            None => {
+                eprintln!("error: invalid entry point {} (expected bank:addr or a label)", arg);
+                process::exit(1);
+            }
+        }
+    }
+
+    let dis = Disassembler::new(&rom);
+    let trace = dis.trace(&entries);
+
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    if let Err(e) = dis.dump(&mut out, &trace, &symbols) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,40 @@
+//! GDB remote serial protocol server.
+//!
+//! Usage: `gbgdb <rom> [port]`. Listens on localhost (port 1234 by default) and waits for a
+//! client, e.g. `target remote localhost:1234` from GDB.
+use emulator::{cartridge, debugger::debugger::Debugger, gdb::gdb, Builder};
+use std::{env, fs, process};
+
+const DEFAULT_PORT: u16 = 1234;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("usage: {} <rom> [port]", args[0]);
+        process::exit(1);
+    }
+    let port = match args.get(2).map(|port| port.parse()) {
+        Some(Ok(port)) => port,
+        Some(Err(_)) => {
+            eprintln!("error: invalid port {}", args[2]);
+            process::exit(1);
+        }
+        None => DEFAULT_PORT,
+    };
+    let rom = fs::read(&args[1]).unwrap_or_else(|e| {
+        eprintln!("error: failed to read {}: {}", args[1], e);
+        process::exit(1);
+    });
+    let cartridge = cartridge::from_bytes(&rom).unwrap_or_else(|_| {
+        eprintln!("error: unsupported cartridge type");
+        process::exit(1);
+    });
+
+    let gb = Builder::default().cartridge(cartridge).skip_boot().build();
+    let mut dbg = Debugger::new(gb);
+    eprintln!("listening on localhost:{}", port);
+    if let Err(e) = gdb::serve(&mut dbg, ("127.0.0.1", port)) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,247 @@
+//! Headless frontend, for batch runs.
+//!
+//! Usage: `gbrun [options] <rom>`. Runs the ROM without opening a window, then writes the
+//! requested outputs. Type `gbrun --help` for the list of options.
+//!
+//! Builds without SDL: `cargo build --release --no-default-features --features png --bin gbrun`.
+use emulator::{
+    apu::device::Stereo44100,
+    cartridge,
+    debugger::debugger::Condition,
+    device::device::Device,
+    joypad::joypad::{Btn, Dir, Key},
+    ppu::ppu::{palette::Color, Framebuffer, LCD_HEIGHT, LCD_WIDTH},
+    Builder, CLOCK, FRAME_CYCLES,
+};
+use std::{
+    env, fs,
+    io::{self, BufWriter, Write},
+    process,
+};
+
+const USAGE: &str = "\
+usage: gbrun [options] <rom>
+  --frames <n>          run for at most n frames (default 600)
+  --until <cond>        stop as soon as cond holds (e.g. `pc==$0150`, see gbdb)
+  --until-serial <text> stop once text has been sent on the serial port
+  --input <file>        scripted input: one `<frame> <key> down|up` per line, where key is
+                        a, b, select, start, up, down, left or right
+  --dmg, --cgb          force the model (default from the cartridge header)
+  --permissive          allow VRAM and OAM accesses in every PPU mode
+  --screenshot <file>   write the last frame as a PNG
+  --ram <file>          write work RAM (C000-DFFF) followed by HRAM (FF80-FFFE)
+  --audio <file>        write the audio as a 16-bit stereo WAV
+  --serial <file>       write the bytes sent on the serial port";
+
+const SAMPLE_RATE: u64 = 44_100;
+
+#[derive(Default)]
+struct Options {
+    rom: Option<String>,
+    frames: Option<u64>,
+    until: Option<Condition>,
+    until_serial: Option<String>,
+    input: Vec<(u64, Key, bool)>,
+    cgb: Option<bool>,
+    permissive: bool,
+    screenshot: Option<String>,
+    ram: Option<String>,
+    audio: Option<String>,
+    serial: Option<String>,
+}
+
+fn main() {
+    let opts = parse_args().unwrap_or_else(|e| {
+        eprintln!("error: {}\n{}", e, USAGE);
+        process::exit(1);
+    });
+    let path = opts.rom.as_deref().unwrap_or_default();
+    let rom = fs::read(path).unwrap_or_else(|e| {
+        eprintln!("error: failed to read {}: {}", path, e);
+        process::exit(1);
+    });
+    let cartridge = cartridge::from_bytes(&rom).unwrap_or_else(|_| {
+        eprintln!("error: unsupported cartridge type");
+        process::exit(1);
+    });
+
+    let builder = Builder::default().cartridge(cartridge)
+                                    .video(Framebuffer::default())
+                                    .audio::<Stereo44100<i16>>()
+                                    .skip_boot();
+    // CGB flag of the header.
+    let cgb = opts.cgb.unwrap_or_else(|| rom.get(0x143).is_some_and(|f| f & 0x80 != 0));
+    let mut gb = if cgb { builder.gbc_mode().build() } else { builder.gb_mode().build() };
+    gb.mmu_mut().ppu_mut().set_permissive(opts.permissive);
+    let samples = gb.mmu().apu().samples();
+
+    let mut audio = Vec::new();
+    // Audio samples owed, in units of 1/CLOCK samples.
+    let mut owed = 0;
+    let mut stopped = None;
+    for frame in 0..opts.frames.unwrap_or(600) {
+        for &(_, key, down) in opts.input.iter().filter(|(f, ..)| *f == frame) {
+            let joypad = gb.mmu_mut().joypad_mut();
+            if down {
+                joypad.press(key);
+            } else {
+                joypad.release(key);
+            }
+        }
+
+        if let Some(cond) = &opts.until {
+            if gb.emulate_frame_until(|gb| cond.eval(gb)) {
+                stopped = Some(format!("{} at frame {}", cond, frame));
+                break;
+            }
+        } else {
+            gb.emulate_frame();
+        }
+
+        owed += FRAME_CYCLES * SAMPLE_RATE;
+        let n = owed / CLOCK;
+        owed %= CLOCK;
+        audio.extend(samples.lock().take(2 * n as usize));
+
+        if let Some(text) = &opts.until_serial {
+            if String::from_utf8_lossy(gb.mmu().serial().output()).contains(text.as_str()) {
+                stopped = Some(format!("serial output at frame {}", frame));
+                break;
+            }
+        }
+    }
+    match stopped {
+        Some(reason) => eprintln!("stopped: {}", reason),
+        None => eprintln!("stopped: frame limit"),
+    }
+
+    let mmu = gb.mmu();
+    let result = (|| -> io::Result<()> {
+        if let Some(path) = &opts.screenshot {
+            write_png(fs::File::create(path)?, mmu.ppu().video().pixels())?;
+        }
+        if let Some(path) = &opts.ram {
+            let ram: Vec<u8> = (0xc000..=0xdfff).chain(0xff80..=0xfffe).map(|a| mmu.read(a)).collect();
+            fs::write(path, ram)?;
+        }
+        if let Some(path) = &opts.audio {
+            write_wav(fs::File::create(path)?, &audio)?;
+        }
+        if let Some(path) = &opts.serial {
+            fs::write(path, mmu.serial().output())?;
+        }
+        Ok(())
+    })();
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut opts = Options::default();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("missing value for {}", arg));
+        match arg.as_str() {
+            "--frames" => opts.frames = Some(value()?.parse().map_err(|_| "invalid frame count")?),
+            "--until" => opts.until = Some(value()?.parse().map_err(|_| "invalid condition")?),
+            "--until-serial" => opts.until_serial = Some(value()?),
+            "--input" => {
+                let path = value()?;
+                let script = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+                opts.input = parse_input(&script)?;
+            }
+            "--dmg" => opts.cgb = Some(false),
+            "--cgb" => opts.cgb = Some(true),
+            "--permissive" => opts.permissive = true,
+            "--screenshot" => opts.screenshot = Some(value()?),
+            "--ram" => opts.ram = Some(value()?),
+            "--audio" => opts.audio = Some(value()?),
+            "--serial" => opts.serial = Some(value()?),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ if opts.rom.is_none() => opts.rom = Some(arg),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+    if opts.rom.is_none() {
+        return Err("missing rom".to_string());
+    }
+    Ok(opts)
+}
+
+fn parse_input(script: &str) -> Result<Vec<(u64, Key, bool)>, String> {
+    let mut input = Vec::new();
+    for (i, line) in script.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let invalid = || format!("input line {}: expected `<frame> <key> down|up`", i + 1);
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [frame, key, action] = fields[..] else {
+            return Err(invalid());
+        };
+        let frame = frame.parse().map_err(|_| invalid())?;
+        let key = match key.to_ascii_lowercase().as_str() {
+            "a" => Key::Btn(Btn::A),
+            "b" => Key::Btn(Btn::B),
+            "select" => Key::Btn(Btn::Select),
+            "start" => Key::Btn(Btn::Start),
+            "up" => Key::Dir(Dir::Up),
+            "down" => Key::Dir(Dir::Down),
+            "left" => Key::Dir(Dir::Left),
+            "right" => Key::Dir(Dir::Right),
+            _ => return Err(invalid()),
+        };
+        let down = match action {
+            "down" => true,
+            "up" => false,
+            _ => return Err(invalid()),
+        };
+        input.push((frame, key, down));
+    }
+    Ok(input)
+}
+
+// Writes an RGB PNG.
+fn write_png<W: Write>(out: W, pixels: &[Color]) -> io::Result<()> {
+    // No frame was drawn if the LCD was never turned on.
+    let data: Vec<u8> = if pixels.len() == LCD_WIDTH * LCD_HEIGHT {
+        pixels.iter().flatten().copied().collect()
+    } else {
+        vec![0xff; 3 * LCD_WIDTH * LCD_HEIGHT]
+    };
+    let mut encoder = png::Encoder::new(BufWriter::new(out), LCD_WIDTH as u32, LCD_HEIGHT as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    Ok(writer.finish()?)
+}
+
+// Writes 16-bit interleaved stereo samples as a WAV file.
+fn write_wav<W: Write>(out: W, samples: &[i16]) -> io::Result<()> {
+    let mut out = BufWriter::new(out);
+    let data_len = (samples.len() * 2) as u32;
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_len).to_le_bytes())?;
+    out.write_all(b"WAVEfmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?; // PCM
+    out.write_all(&2u16.to_le_bytes())?; // channels
+    out.write_all(&(SAMPLE_RATE as u32).to_le_bytes())?;
+    out.write_all(&(SAMPLE_RATE as u32 * 4).to_le_bytes())?; // bytes per second
+    out.write_all(&4u16.to_le_bytes())?; // bytes per frame
+    out.write_all(&16u16.to_le_bytes())?; // bits per sample
+    out.write_all(b"data")?;
+    out.write_all(&data_len.to_le_bytes())?;
+    for sample in samples {
+        out.write_all(&sample.to_le_bytes())?;
+    }
+    out.flush()
+}
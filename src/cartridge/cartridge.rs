@@ -12,14 +12,29 @@ pub use mbc5::Mbc5;
 pub use rom::Rom;
 
 
-pub trait Cartridge: Device {}
+pub trait Cartridge: Device {
+    /// Returns the raw ROM image, or an empty slice if there is none.
+    fn rom(&self) -> &[u8] {
+        &[]
+    }
+
+    /// Returns the ROM bank currently mapped at 0x4000-0x7fff.
+    fn rom_bank(&self) -> usize {
+        1
+    }
+}
 
 impl Cartridge for () {}
-impl Cartridge for Rom {}
-impl Cartridge for Mbc1 {}
-impl Cartridge for Mbc3 {}
-impl Cartridge for Mbc5 {}
-impl Cartridge for Box<dyn Cartridge> {}
+
+impl Cartridge for Box<dyn Cartridge> {
+    fn rom(&self) -> &[u8] {
+        self.as_ref().rom()
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.as_ref().rom_bank()
+    }
+}
 
 impl Device for Box<dyn Cartridge> {
     fn read(&self, addr: u16) -> u8 {
@@ -1,4 +1,7 @@
-use crate::{cartridge::cartridge::ram_banks, device::device::Device};
+use crate::{
+    cartridge::cartridge::{ram_banks, Cartridge},
+    device::device::Device,
+};
 
 enum Mode {
     Rom,
@@ -87,3 +90,13 @@ impl Device for Mbc1 {
         }
     }
 }
+
+impl Cartridge for Mbc1 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank.max(1)
+    }
+}
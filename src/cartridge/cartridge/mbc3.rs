@@ -1,4 +1,7 @@
-use crate::{cartridge::cartridge::ram_banks, device::device::Device};
+use crate::{
+    cartridge::cartridge::{ram_banks, Cartridge},
+    device::device::Device,
+};
 
 enum Mode {
     Ram,
@@ -93,3 +96,13 @@ impl Device for Mbc3 {
         }
     }
 }
+
+impl Cartridge for Mbc3 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank.max(1)
+    }
+}
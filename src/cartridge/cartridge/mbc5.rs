@@ -1,4 +1,7 @@
-use crate::{cartridge::cartridge::ram_banks, device::device::Device};
+use crate::{
+    cartridge::cartridge::{ram_banks, Cartridge},
+    device::device::Device,
+};
 
 /// MBC5 controller.
 pub struct Mbc5 {
@@ -74,3 +77,13 @@ impl Device for Mbc5 {
         }
     }
 }
+
+impl Cartridge for Mbc5 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank
+    }
+}
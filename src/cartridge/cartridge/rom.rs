@@ -1,4 +1,4 @@
-use crate::device::device::Device;
+use crate::{cartridge::cartridge::Cartridge, device::device::Device};
 
 pub struct Rom {
     rom: Box<[u8]>,
@@ -29,3 +29,13 @@ impl Device for Rom {
         }
     }
 }
+
+impl Cartridge for Rom {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn rom_bank(&self) -> usize {
+        1
+    }
+}
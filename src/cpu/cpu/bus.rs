@@ -0,0 +1,43 @@
+pub mod cdl;
+
+/// Memory bus the CPU runs against.
+///
+/// The CPU only talks to the rest of the system through this trait, so it can run against the
+/// full [`Mmu`](crate::mmu::mmu::Mmu), a flat memory for testing, a bus that records or traces
+/// every access, or any other system design.
+///
+/// The bus is ticked once per M-cycle, before every memory access and for every internal
+/// cycle, so the rest of the system can be kept in lockstep with the CPU.
+pub trait Bus {
+    /// Reads a byte.
+    fn read(&mut self, addr: u16) -> u8;
+
+    /// Writes a byte.
+    fn write(&mut self, addr: u16, data: u8);
+
+    /// Advances the rest of the system by one M-cycle.
+    fn tick(&mut self);
+
+    /// Returns the interrupts that are both requested and enabled, as a mask of the IE/IF
+    /// bits (VBlank is bit 0, joypad is bit 4).
+    fn pending(&self) -> u8 {
+        0
+    }
+
+    /// Clears the request of the interrupt at bit `int`, when it is dispatched.
+    fn acknowledge(&mut self, _int: u8) {}
+
+    /// Returns the ROM bank mapped at 0x4000-0x7fff.
+    fn rom_bank(&self) -> usize {
+        1
+    }
+
+    /// Records an access to `addr` in the code/data log, if any. `flag` is one of the
+    /// [`cdl`] flags.
+    fn log(&mut self, _addr: u16, _flag: u8) {}
+
+    /// Performs a pending CGB speed switch, on STOP. Returns true if the speed was switched.
+    fn speed_switch(&mut self) -> bool {
+        false
+    }
+}
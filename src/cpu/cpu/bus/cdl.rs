@@ -0,0 +1,8 @@
+//! Code/data log flags, passed to [`Bus::log`](super::Bus::log).
+
+/// Byte was executed (opcode or immediate operand).
+pub const CODE: u8 = 0x01;
+/// Byte was read as data.
+pub const DATA: u8 = 0x02;
+/// Byte was written. Only used for RAM; writes to ROM go to the cartridge controller.
+pub const WRITE: u8 = 0x04;
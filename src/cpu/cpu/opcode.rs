@@ -0,0 +1,458 @@
+use std::fmt;
+
+/// Instruction mnemonics of the SM83 core.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Mnemonic {
+    Nop,
+    Ld,
+    Ldh,
+    Inc,
+    Dec,
+    Add,
+    Adc,
+    Sub,
+    Sbc,
+    And,
+    Xor,
+    Or,
+    Cp,
+    Rlca,
+    Rrca,
+    Rla,
+    Rra,
+    Daa,
+    Cpl,
+    Scf,
+    Ccf,
+    Jr,
+    Jp,
+    Call,
+    Ret,
+    Reti,
+    Rst,
+    Push,
+    Pop,
+    Halt,
+    Stop,
+    Di,
+    Ei,
+    /// The 0xCB prefix of the bit manipulation instructions.
+    Prefix,
+    Rlc,
+    Rrc,
+    Rl,
+    Rr,
+    Sla,
+    Sra,
+    Swap,
+    Srl,
+    Bit,
+    Res,
+    Set,
+    /// One of the 11 unused opcodes. Locks up the CPU.
+    Illegal,
+}
+
+/// Branch conditions.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Cond {
+    NZ,
+    Z,
+    NC,
+    C,
+}
+
+/// Instruction operands.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Operand {
+    A,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+    /// (BC)
+    IndBC,
+    /// (DE)
+    IndDE,
+    /// (HL)
+    IndHL,
+    /// (HL+)
+    IndHLInc,
+    /// (HL-)
+    IndHLDec,
+    /// (FF00+C)
+    IndC,
+    /// 8-bit immediate data.
+    D8,
+    /// 16-bit immediate data.
+    D16,
+    /// (FF00+a8)
+    IndA8,
+    /// 16-bit immediate address (jump and call targets).
+    A16,
+    /// (a16)
+    IndA16,
+    /// 8-bit signed immediate data.
+    R8,
+    /// SP plus an 8-bit signed immediate.
+    SPR8,
+    Cond(Cond),
+    /// Bit index of the BIT, RES and SET instructions.
+    Bit(u8),
+    /// Restart vector of the RST instruction.
+    Vec(u8),
+}
+
+impl Operand {
+    /// Returns the number of immediate bytes that follow the opcode.
+    pub const fn imm_len(self) -> u8 {
+        match self {
+            Operand::D8 | Operand::IndA8 | Operand::R8 | Operand::SPR8 => 1,
+            Operand::D16 | Operand::A16 | Operand::IndA16 => 2,
+            _ => 0,
+        }
+    }
+}
+
+/// Effect of an instruction on one of the flags of the F register.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Effect {
+    Unaffected,
+    Reset,
+    Set,
+    /// Depends on the result of the operation.
+    Affected,
+}
+
+/// Effects of an instruction on the Z, N, H and C flags.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct Flags {
+    pub z: Effect,
+    pub n: Effect,
+    pub h: Effect,
+    pub c: Effect,
+}
+
+impl Flags {
+    // Parses the notation used in the opcode tables (e.g. `b"Z0HC"`).
+    const fn parse(s: &[u8; 4]) -> Self {
+        const fn effect(c: u8) -> Effect {
+            match c {
+                b'-' => Effect::Unaffected,
+                b'0' => Effect::Reset,
+                b'1' => Effect::Set,
+                _ => Effect::Affected,
+            }
+        }
+        Self { z: effect(s[0]),
+               n: effect(s[1]),
+               h: effect(s[2]),
+               c: effect(s[3]) }
+    }
+}
+
+/// A decoded instruction.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct Instr {
+    pub mnemonic: Mnemonic,
+    pub operands: [Option<Operand>; 2],
+    /// Length in bytes, including the opcode (and the 0xCB prefix).
+    pub len: u8,
+    /// Duration in M-cycles. For conditional instructions, when the branch is not taken.
+    pub cycles: u8,
+    /// Duration in M-cycles of conditional instructions when the branch is taken.
+    pub branch_cycles: Option<u8>,
+    pub flags: Flags,
+}
+
+impl Instr {
+    const ILLEGAL: Instr = Instr::new(Mnemonic::Illegal, [None, None], 1, b"----");
+
+    const fn new(mnemonic: Mnemonic, operands: [Option<Operand>; 2], cycles: u8, flags: &[u8; 4]) -> Self {
+        let mut len = 1;
+        if let Some(op) = operands[0] {
+            len += op.imm_len();
+        }
+        if let Some(op) = operands[1] {
+            len += op.imm_len();
+        }
+        Self { mnemonic,
+               operands,
+               len,
+               cycles,
+               branch_cycles: None,
+               flags: Flags::parse(flags) }
+    }
+
+    const fn branch(mut self, cycles: u8) -> Self {
+        self.branch_cycles = Some(cycles);
+        self
+    }
+
+    const fn prefixed(mut self) -> Self {
+        self.len += 1;
+        self
+    }
+
+    /// Returns the branch condition, if the instruction is conditional.
+    pub fn cond(&self) -> Option<Cond> {
+        match self.operands[0] {
+            Some(Operand::Cond(cond)) => Some(cond),
+            _ => None,
+        }
+    }
+
+    /// Returns the operands of the instruction.
+    pub fn operands(&self) -> impl Iterator<Item = Operand> + '_ {
+        self.operands.iter().flatten().copied()
+    }
+}
+
+/// Decoded instructions, indexed by opcode.
+pub static OPCODES: [Instr; 256] = opcodes();
+
+/// Decoded 0xCB-prefixed instructions, indexed by the byte that follows the prefix.
+pub static CB_OPCODES: [Instr; 256] = cb_opcodes();
+
+/// Returns the instruction that begins with the given `opcode`. `next` is the byte that
+/// follows it, which is only used when `opcode` is the 0xCB prefix.
+pub fn decode(opcode: u8, next: u8) -> &'static Instr {
+    if opcode == 0xcb {
+        &CB_OPCODES[next as usize]
+    } else {
+        &OPCODES[opcode as usize]
+    }
+}
+
+// Register operands as encoded in bits 0-2 and 3-5 of the opcodes.
+const R: [Operand; 8] = [Operand::B,
+                         Operand::C,
+                         Operand::D,
+                         Operand::E,
+                         Operand::H,
+                         Operand::L,
+                         Operand::IndHL,
+                         Operand::A];
+// Register pair operands as encoded in bits 4-5.
+const RP: [Operand; 4] = [Operand::BC, Operand::DE, Operand::HL, Operand::SP];
+// Register pair operands of PUSH and POP.
+const RP2: [Operand; 4] = [Operand::BC, Operand::DE, Operand::HL, Operand::AF];
+const CC: [Cond; 4] = [Cond::NZ, Cond::Z, Cond::NC, Cond::C];
+
+// Accessing (HL) takes an extra M-cycle.
+const fn hl(r: usize) -> u8 {
+    if r == 6 {
+        1
+    } else {
+        0
+    }
+}
+
+const fn opcodes() -> [Instr; 256] {
+    use Mnemonic::*;
+    use Operand::*;
+
+    let mut table = [Instr::ILLEGAL; 256];
+    let mut i = 0;
+    while i < 256 {
+        let op = i as u8;
+        let x = op >> 6;
+        let y = ((op >> 3) & 0x7) as usize;
+        let z = (op & 0x7) as usize;
+        let p = y >> 1;
+        let q = y & 0x1;
+
+        #[rustfmt::skip]
+        let instr = match (x, z) {
+            (0, 0) => match y {
+                0 => Instr::new(Nop, [None, None], 1, b"----"),
+                1 => Instr::new(Ld, [Some(IndA16), Some(SP)], 5, b"----"),
+                // STOP is followed by a byte that is fetched and discarded.
+                2 => { let mut stop = Instr::new(Stop, [None, None], 2, b"----"); stop.len = 2; stop }
+                3 => Instr::new(Jr, [Some(R8), None], 3, b"----"),
+                _ => Instr::new(Jr, [Some(Cond(CC[y - 4])), Some(R8)], 2, b"----").branch(3),
+            },
+            (0, 1) if q == 0 => Instr::new(Ld, [Some(RP[p]), Some(D16)], 3, b"----"),
+            (0, 1) => Instr::new(Add, [Some(HL), Some(RP[p])], 2, b"-0HC"),
+            (0, 2) => {
+                let ind = [IndBC, IndDE, IndHLInc, IndHLDec][p];
+                if q == 0 {
+                    Instr::new(Ld, [Some(ind), Some(A)], 2, b"----")
+                } else {
+                    Instr::new(Ld, [Some(A), Some(ind)], 2, b"----")
+                }
+            }
+            (0, 3) if q == 0 => Instr::new(Inc, [Some(RP[p]), None], 2, b"----"),
+            (0, 3) => Instr::new(Dec, [Some(RP[p]), None], 2, b"----"),
+            (0, 4) => Instr::new(Inc, [Some(R[y]), None], 1 + 2 * hl(y), b"Z0H-"),
+            (0, 5) => Instr::new(Dec, [Some(R[y]), None], 1 + 2 * hl(y), b"Z1H-"),
+            (0, 6) => Instr::new(Ld, [Some(R[y]), Some(D8)], 2 + hl(y), b"----"),
+            (0, _) => match y {
+                0 => Instr::new(Rlca, [None, None], 1, b"000C"),
+                1 => Instr::new(Rrca, [None, None], 1, b"000C"),
+                2 => Instr::new(Rla, [None, None], 1, b"000C"),
+                3 => Instr::new(Rra, [None, None], 1, b"000C"),
+                4 => Instr::new(Daa, [None, None], 1, b"Z-0C"),
+                5 => Instr::new(Cpl, [None, None], 1, b"-11-"),
+                6 => Instr::new(Scf, [None, None], 1, b"-001"),
+                _ => Instr::new(Ccf, [None, None], 1, b"-00C"),
+            },
+            (1, 6) if y == 6 => Instr::new(Halt, [None, None], 1, b"----"),
+            (1, _) => Instr::new(Ld, [Some(R[y]), Some(R[z])], 1 + hl(y) + hl(z), b"----"),
+            (2, _) => alu(y, R[z], 1 + hl(z)),
+            (3, 0) => match y {
+                0..=3 => Instr::new(Ret, [Some(Cond(CC[y])), None], 2, b"----").branch(5),
+                4 => Instr::new(Ldh, [Some(IndA8), Some(A)], 3, b"----"),
+                5 => Instr::new(Add, [Some(SP), Some(R8)], 4, b"00HC"),
+                6 => Instr::new(Ldh, [Some(A), Some(IndA8)], 3, b"----"),
+                _ => Instr::new(Ld, [Some(HL), Some(SPR8)], 3, b"00HC"),
+            },
+            (3, 1) if q == 0 && p == 3 => Instr::new(Pop, [Some(AF), None], 3, b"ZNHC"),
+            (3, 1) if q == 0 => Instr::new(Pop, [Some(RP2[p]), None], 3, b"----"),
+            (3, 1) => match p {
+                0 => Instr::new(Ret, [None, None], 4, b"----"),
+                1 => Instr::new(Reti, [None, None], 4, b"----"),
+                2 => Instr::new(Jp, [Some(HL), None], 1, b"----"),
+                _ => Instr::new(Ld, [Some(SP), Some(HL)], 2, b"----"),
+            },
+            (3, 2) => match y {
+                0..=3 => Instr::new(Jp, [Some(Cond(CC[y])), Some(A16)], 3, b"----").branch(4),
+                4 => Instr::new(Ld, [Some(IndC), Some(A)], 2, b"----"),
+                5 => Instr::new(Ld, [Some(IndA16), Some(A)], 4, b"----"),
+                6 => Instr::new(Ld, [Some(A), Some(IndC)], 2, b"----"),
+                _ => Instr::new(Ld, [Some(A), Some(IndA16)], 4, b"----"),
+            },
+            (3, 3) => match y {
+                0 => Instr::new(Jp, [Some(A16), None], 4, b"----"),
+                1 => Instr::new(Prefix, [None, None], 1, b"----"),
+                6 => Instr::new(Di, [None, None], 1, b"----"),
+                7 => Instr::new(Ei, [None, None], 1, b"----"),
+                _ => Instr::ILLEGAL,
+            },
+            (3, 4) if y < 4 => Instr::new(Call, [Some(Cond(CC[y])), Some(A16)], 3, b"----").branch(6),
+            (3, 4) => Instr::ILLEGAL,
+            (3, 5) if q == 0 => Instr::new(Push, [Some(RP2[p]), None], 4, b"----"),
+            (3, 5) if p == 0 => Instr::new(Call, [Some(A16), None], 6, b"----"),
+            (3, 5) => Instr::ILLEGAL,
+            (3, 6) => alu(y, D8, 2),
+            _ => Instr::new(Rst, [Some(Vec(op & 0x38)), None], 4, b"----"),
+        };
+        table[i] = instr;
+        i += 1;
+    }
+    table
+}
+
+// 8-bit arithmetic and logic instructions, encoded in bits 3-5.
+const fn alu(y: usize, src: Operand, cycles: u8) -> Instr {
+    use Mnemonic::*;
+
+    match y {
+        0 => Instr::new(Add, [Some(Operand::A), Some(src)], cycles, b"Z0HC"),
+        1 => Instr::new(Adc, [Some(Operand::A), Some(src)], cycles, b"Z0HC"),
+        2 => Instr::new(Sub, [Some(src), None], cycles, b"Z1HC"),
+        3 => Instr::new(Sbc, [Some(Operand::A), Some(src)], cycles, b"Z1HC"),
+        4 => Instr::new(And, [Some(src), None], cycles, b"Z010"),
+        5 => Instr::new(Xor, [Some(src), None], cycles, b"Z000"),
+        6 => Instr::new(Or, [Some(src), None], cycles, b"Z000"),
+        _ => Instr::new(Cp, [Some(src), None], cycles, b"Z1HC"),
+    }
+}
+
+const fn cb_opcodes() -> [Instr; 256] {
+    use Mnemonic::*;
+
+    let mut table = [Instr::ILLEGAL; 256];
+    let mut i = 0;
+    while i < 256 {
+        let op = i as u8;
+        let y = (op >> 3) & 0x7;
+        let r = R[(op & 0x7) as usize];
+        // Accessing (HL) takes an extra M-cycle for reading and another one for writing back.
+        let ind = matches!(r, Operand::IndHL);
+        let rmw = if ind { 4 } else { 2 };
+
+        #[rustfmt::skip]
+        let instr = match op >> 6 {
+            0 => {
+                let (mnemonic, flags) = match y {
+                    0 => (Rlc, b"Z00C"),
+                    1 => (Rrc, b"Z00C"),
+                    2 => (Rl, b"Z00C"),
+                    3 => (Rr, b"Z00C"),
+                    4 => (Sla, b"Z00C"),
+                    5 => (Sra, b"Z00C"),
+                    6 => (Swap, b"Z000"),
+                    _ => (Srl, b"Z00C"),
+                };
+                Instr::new(mnemonic, [Some(r), None], rmw, flags)
+            }
+            1 => Instr::new(Bit, [Some(Operand::Bit(y)), Some(r)], if ind { 3 } else { 2 }, b"Z01-"),
+            2 => Instr::new(Res, [Some(Operand::Bit(y)), Some(r)], rmw, b"----"),
+            _ => Instr::new(Set, [Some(Operand::Bit(y)), Some(r)], rmw, b"----"),
+        };
+        table[i] = instr.prefixed();
+        i += 1;
+    }
+    table
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Mnemonic::Prefix => "PREFIX CB",
+            Mnemonic::Illegal => "ILLEGAL",
+            _ => return write!(f, "{}", format!("{:?}", self).to_uppercase()),
+        };
+        f.write_str(s)
+    }
+}
+
+impl fmt::Display for Cond {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::IndBC => f.write_str("(BC)"),
+            Operand::IndDE => f.write_str("(DE)"),
+            Operand::IndHL => f.write_str("(HL)"),
+            Operand::IndHLInc => f.write_str("(HL+)"),
+            Operand::IndHLDec => f.write_str("(HL-)"),
+            Operand::IndC => f.write_str("(C)"),
+            Operand::D8 => f.write_str("d8"),
+            Operand::D16 => f.write_str("d16"),
+            Operand::IndA8 => f.write_str("(a8)"),
+            Operand::A16 => f.write_str("a16"),
+            Operand::IndA16 => f.write_str("(a16)"),
+            Operand::R8 => f.write_str("r8"),
+            Operand::SPR8 => f.write_str("SP+r8"),
+            Operand::Cond(cond) => write!(f, "{}", cond),
+            Operand::Bit(bit) => write!(f, "{}", bit),
+            Operand::Vec(vec) => write!(f, "{:02X}H", vec),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+/// Formats the instruction using the notation of the opcode tables (e.g. `LD A,(a16)`).
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.mnemonic)?;
+        for (i, op) in self.operands().enumerate() {
+            let sep = if i == 0 { " " } else { "," };
+            write!(f, "{}{}", sep, op)?;
+        }
+        Ok(())
+    }
+}
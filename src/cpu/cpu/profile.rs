@@ -0,0 +1,93 @@
+use crate::disasm::symbols::Symbols;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, Write},
+};
+
+/// Execution profiler.
+///
+/// Accumulates the CPU cycles spent at every `bank:pc`, and counts the calls to every
+/// `CALL`/`RST` target and interrupt vector. Cycles spent halted are attributed to the
+/// instruction following the HALT, and interrupt dispatches to the vector. Addresses outside of
+/// the ROM use bank 0.
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    cycles: HashMap<(usize, u16), u64>,
+    calls: HashMap<(usize, u16), u64>,
+    total: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the total number of profiled CPU cycles.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Returns the `n` locations where the most cycles were spent, hottest first.
+    pub fn hot_spots(&self, n: usize) -> Vec<((usize, u16), u64)> {
+        top(&self.cycles, n)
+    }
+
+    /// Returns the `n` most called locations, most called first.
+    pub fn calls(&self, n: usize) -> Vec<((usize, u16), u64)> {
+        top(&self.calls, n)
+    }
+
+    /// Returns the cycles spent in each bank.
+    pub fn banks(&self) -> BTreeMap<usize, u64> {
+        let mut banks = BTreeMap::new();
+        for (&(bank, _), &cycles) in &self.cycles {
+            *banks.entry(bank).or_default() += cycles;
+        }
+        banks
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Writes a report with the cycles per bank and the top `n` hot spots and call targets.
+    pub fn report<W: Write>(&self, mut out: W, n: usize, symbols: Option<&Symbols>) -> io::Result<()> {
+        let total = self.total.max(1) as f64;
+        let loc = |(bank, addr): (usize, u16)| match symbols.and_then(|s| s.nearest(bank, addr)) {
+            Some((name, 0)) => format!("{:02X}:{:04X} {}", bank, addr, name),
+            Some((name, offset)) => format!("{:02X}:{:04X} {}+{}", bank, addr, name, offset),
+            None => format!("{:02X}:{:04X}", bank, addr),
+        };
+
+        writeln!(out, "total: {} cycles", self.total)?;
+        writeln!(out, "\ncycles per bank:")?;
+        for (bank, cycles) in self.banks() {
+            writeln!(out, "  {:02X}  {:>12}  {:5.1}%", bank, cycles, 100.0 * cycles as f64 / total)?;
+        }
+        writeln!(out, "\nhot spots:")?;
+        for (at, cycles) in self.hot_spots(n) {
+            writeln!(out, "  {:>12}  {:5.1}%  {}", cycles, 100.0 * cycles as f64 / total, loc(at))?;
+        }
+        writeln!(out, "\ncalls:")?;
+        for (at, calls) in self.calls(n) {
+            writeln!(out, "  {:>12}  {}", calls, loc(at))?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn add_cycles(&mut self, bank: usize, pc: u16, cycles: u64) {
+        *self.cycles.entry((bank, pc)).or_default() += cycles;
+        self.total += cycles;
+    }
+
+    pub(crate) fn add_call(&mut self, bank: usize, addr: u16) {
+        *self.calls.entry((bank, addr)).or_default() += 1;
+    }
+}
+
+fn top(map: &HashMap<(usize, u16), u64>, n: usize) -> Vec<((usize, u16), u64)> {
+    let mut entries: Vec<_> = map.iter().map(|(&at, &count)| (at, count)).collect();
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    entries.truncate(n);
+    entries
+}
@@ -0,0 +1,129 @@
+use super::registers::Registers;
+use crate::disasm::symbols::Symbols;
+use std::{fmt, io, io::Write, ops::RangeInclusive};
+
+/// Instruction tracer.
+///
+/// Writes a line per executed instruction, before it executes, in the format used by
+/// [Gameboy Doctor](https://github.com/robert/gameboy-doctor):
+///
+/// ```text
+/// A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+/// ```
+///
+/// Interrupt dispatches and the cycles spent halted are not traced.
+pub struct Tracer {
+    out: Box<dyn Write>,
+    enabled: bool,
+    pc: Option<RangeInclusive<u16>>,
+    bank: Option<usize>,
+    symbols: Option<Symbols>,
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    /// Creates an enabled tracer that writes to `out`.
+    pub fn new<W: Write + 'static>(out: W) -> Self {
+        Self { out: Box::new(out),
+               enabled: true,
+               pc: None,
+               bank: None,
+               symbols: None,
+               error: None }
+    }
+
+    /// Only traces instructions whose address lies in `range`.
+    pub fn pc_range(mut self, range: RangeInclusive<u16>) -> Self {
+        self.pc = Some(range);
+        self
+    }
+
+    /// Only traces instructions in the given ROM bank. Bank 0 is 0x0000-0x3fff; addresses
+    /// outside of the ROM never match.
+    pub fn bank(mut self, bank: usize) -> Self {
+        self.bank = Some(bank);
+        self
+    }
+
+    /// Writes a `label:` line before the instructions at a symbol. Note that the output no
+    /// longer matches the Gameboy Doctor format.
+    pub fn symbols(mut self, symbols: Symbols) -> Self {
+        self.symbols = Some(symbols);
+        self
+    }
+
+    /// Resumes tracing.
+    pub fn start(&mut self) {
+        self.enabled = true;
+    }
+
+    /// Pauses tracing.
+    pub fn stop(&mut self) {
+        self.enabled = false;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Returns the error that stopped the tracer, if writing to the output failed.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    // Returns true if the instruction at `pc` in `bank` (`None` outside of the ROM) is traced.
+    pub(crate) fn filter(&self, pc: u16, bank: Option<usize>) -> bool {
+        self.enabled
+        && self.pc.as_ref().is_none_or(|range| range.contains(&pc))
+        && self.bank.is_none_or(|b| Some(b) == bank)
+    }
+
+    pub(crate) fn trace(&mut self, reg: &Registers, bank: Option<usize>, pcmem: [u8; 4]) {
+        if let Err(e) = self.write(reg, bank, pcmem) {
+            self.enabled = false;
+            self.error = Some(e);
+        }
+    }
+
+    fn write(&mut self, reg: &Registers, bank: Option<usize>, pcmem: [u8; 4]) -> io::Result<()> {
+        let label = self.symbols
+                        .as_ref()
+                        .zip(bank)
+                        .and_then(|(symbols, bank)| symbols.get(bank.max(1), reg.pc));
+        if let Some(label) = label {
+            writeln!(self.out, "{}:", label)?;
+        }
+        writeln!(self.out,
+                 "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} \
+                  SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+                 reg.a,
+                 reg.af() as u8,
+                 reg.b,
+                 reg.c,
+                 reg.d,
+                 reg.e,
+                 reg.h,
+                 reg.l,
+                 reg.sp,
+                 reg.pc,
+                 pcmem[0],
+                 pcmem[1],
+                 pcmem[2],
+                 pcmem[3])
+    }
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tracer")
+         .field("enabled", &self.enabled)
+         .field("pc", &self.pc)
+         .field("bank", &self.bank)
+         .field("error", &self.error)
+         .finish_non_exhaustive()
+    }
+}
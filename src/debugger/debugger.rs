@@ -0,0 +1,575 @@
+use crate::{
+    apu::device::Audio,
+    cartridge::cartridge::Cartridge,
+    cpu::cpu::{opcode::Mnemonic, Access, WatchHit},
+    device::device::Device,
+    disasm::{disasm::Instruction, symbols::Symbols},
+    ppu::ppu::Video,
+    GameBoy,
+};
+use std::{fmt, str::FromStr};
+
+/// CPU registers that can be used in break conditions.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Reg {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+    PC,
+}
+
+impl Reg {
+    fn get<C: Cartridge, V: Video, D: Audio>(self, gb: &GameBoy<C, V, D>) -> u16 {
+        let reg = gb.cpu().reg();
+        match self {
+            Reg::A => u16::from(reg.a),
+            Reg::F => reg.af() & 0xff,
+            Reg::B => u16::from(reg.b),
+            Reg::C => u16::from(reg.c),
+            Reg::D => u16::from(reg.d),
+            Reg::E => u16::from(reg.e),
+            Reg::H => u16::from(reg.h),
+            Reg::L => u16::from(reg.l),
+            Reg::AF => reg.af(),
+            Reg::BC => reg.bc(),
+            Reg::DE => reg.de(),
+            Reg::HL => reg.hl(),
+            Reg::SP => reg.sp,
+            Reg::PC => reg.pc,
+        }
+    }
+}
+
+impl FromStr for Reg {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_uppercase().as_str() {
+            "A" => Reg::A,
+            "F" => Reg::F,
+            "B" => Reg::B,
+            "C" => Reg::C,
+            "D" => Reg::D,
+            "E" => Reg::E,
+            "H" => Reg::H,
+            "L" => Reg::L,
+            "AF" => Reg::AF,
+            "BC" => Reg::BC,
+            "DE" => Reg::DE,
+            "HL" => Reg::HL,
+            "SP" => Reg::SP,
+            "PC" => Reg::PC,
+            _ => return Err(()),
+        })
+    }
+}
+
+/// Comparison operators of break conditions.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Cmp {
+    fn symbol(self) -> &'static str {
+        match self {
+            Cmp::Eq => "==",
+            Cmp::Ne => "!=",
+            Cmp::Lt => "<",
+            Cmp::Le => "<=",
+            Cmp::Gt => ">",
+            Cmp::Ge => ">=",
+        }
+    }
+}
+
+/// Condition on the value of a register (e.g. `A==$3F`).
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct Condition {
+    pub reg: Reg,
+    pub cmp: Cmp,
+    pub value: u16,
+}
+
+impl Condition {
+    pub fn eval<C: Cartridge, V: Video, D: Audio>(&self, gb: &GameBoy<C, V, D>) -> bool {
+        let reg = self.reg.get(gb);
+        match self.cmp {
+            Cmp::Eq => reg == self.value,
+            Cmp::Ne => reg != self.value,
+            Cmp::Lt => reg < self.value,
+            Cmp::Le => reg <= self.value,
+            Cmp::Gt => reg > self.value,
+            Cmp::Ge => reg >= self.value,
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Two-character operators first so that `<=` isn't parsed as `<`.
+        let ops = [Cmp::Eq, Cmp::Ne, Cmp::Le, Cmp::Ge, Cmp::Lt, Cmp::Gt];
+        let (cmp, (reg, value)) = ops.iter()
+                                     .find_map(|&cmp| Some((cmp, s.split_once(cmp.symbol())?)))
+                                     .ok_or(())?;
+        Ok(Self { reg: reg.trim().parse()?,
+                  cmp,
+                  value: parse_number(value.trim()).ok_or(())? })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}{}${:X}", self.reg, self.cmp.symbol(), self.value)
+    }
+}
+
+/// Parses a hex number, optionally prefixed with `$` or `0x`.
+pub fn parse_number(s: &str) -> Option<u16> {
+    let hex = s.strip_prefix('$').or_else(|| s.strip_prefix("0x")).unwrap_or(s);
+    u16::from_str_radix(hex, 16).ok()
+}
+
+/// PC breakpoint.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct Breakpoint {
+    /// ROM bank the breakpoint is restricted to. Only checked for addresses in the switchable
+    /// bank area (0x4000-0x7fff).
+    pub bank: Option<usize>,
+    pub addr: u16,
+    /// Additional condition that must hold for the breakpoint to trigger.
+    pub cond: Option<Condition>,
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(bank) = self.bank {
+            write!(f, "{:02X}:", bank)?;
+        }
+        write!(f, "{:04X}", self.addr)?;
+        if let Some(cond) = self.cond {
+            write!(f, " if {}", cond)?;
+        }
+        Ok(())
+    }
+}
+
+/// Events that execution can be run until.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Event {
+    /// The PPU enters the vertical blanking period.
+    VBlank,
+    /// The CPU dispatches an interrupt.
+    Interrupt,
+}
+
+/// Reason execution stopped.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Stop {
+    /// The requested step completed.
+    Step,
+    Breakpoint(Breakpoint),
+    Watchpoint(WatchHit),
+    Condition(Condition),
+    Event(Event),
+    /// VBlank was awaited with the LCD off, or the LCD was turned off while waiting for it.
+    LcdOff,
+    /// The CPU locked up after fetching an illegal opcode.
+    Locked(u8),
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stop::Step => f.write_str("step"),
+            Stop::Breakpoint(bp) => write!(f, "breakpoint {}", bp),
+            Stop::Watchpoint(hit) => {
+                write!(f, "watchpoint: {:?} ${:04X} = ${:02X}", hit.access, hit.addr, hit.data)
+            }
+            Stop::Condition(cond) => write!(f, "condition {}", cond),
+            Stop::Event(event) => write!(f, "{:?}", event),
+            Stop::LcdOff => f.write_str("LCD is off"),
+            Stop::Locked(opcode) => write!(f, "CPU locked by illegal opcode ${:02X}", opcode),
+        }
+    }
+}
+
+// When to stop besides breakpoints, watchpoints and conditions.
+#[derive(Clone, Copy)]
+enum Until {
+    Never,
+    Steps(u64),
+    // Like `Steps`, but breakpoints, watchpoints and conditions are checked first.
+    Budget(u64),
+    // PC reaches the address with SP at or above the given value.
+    Return(u16, u16),
+    // A return pops the stack above the given SP.
+    Out(u16),
+    // Like `Budget`, but also stops at the event.
+    Event(Event, u64),
+}
+
+/// Debugger around a [`GameBoy`].
+pub struct Debugger<C: Cartridge, V: Video, D: Audio> {
+    gb: GameBoy<C, V, D>,
+    breakpoints: Vec<Breakpoint>,
+    conditions: Vec<Condition>,
+    symbols: Symbols,
+}
+
+impl<C: Cartridge, V: Video, D: Audio> Debugger<C, V, D> {
+    pub fn new(gb: GameBoy<C, V, D>) -> Self {
+        Self { gb,
+               breakpoints: Vec::new(),
+               conditions: Vec::new(),
+               symbols: Symbols::new() }
+    }
+
+    pub fn gb(&self) -> &GameBoy<C, V, D> {
+        &self.gb
+    }
+
+    pub fn gb_mut(&mut self) -> &mut GameBoy<C, V, D> {
+        &mut self.gb
+    }
+
+    pub fn into_inner(self) -> GameBoy<C, V, D> {
+        self.gb
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    /// Parses a location given as a symbol, `bank:addr` or `addr` (in hex). The bank is
+    /// `None` if not given explicitly or by the symbol.
+    pub fn parse_location(&self, s: &str) -> Option<(Option<usize>, u16)> {
+        if let Some((bank, addr)) = self.symbols.lookup(s) {
+            return Some((Some(bank), addr));
+        }
+        match s.split_once(':') {
+            Some((bank, addr)) => Some((Some(parse_number(bank)? as usize), parse_number(addr)?)),
+            None => Some((None, parse_number(s)?)),
+        }
+    }
+
+    /// Formats `addr`, as currently mapped, relative to the closest symbol (e.g. `Main+3`).
+    pub fn label(&self, addr: u16) -> String {
+        self.symbols.format(self.bank(addr), addr, u16::MAX)
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn add_breakpoint(&mut self, bp: Breakpoint) {
+        if !self.breakpoints.contains(&bp) {
+            self.breakpoints.push(bp);
+        }
+    }
+
+    /// Removes the breakpoints at `addr`.
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.retain(|bp| bp.addr != addr);
+    }
+
+    /// Removes the breakpoints at `addr` restricted to `bank` (unrestricted ones with `None`).
+    pub fn remove_bank_breakpoint(&mut self, bank: Option<usize>, addr: u16) {
+        self.breakpoints.retain(|bp| bp.bank != bank || bp.addr != addr);
+    }
+
+    /// Returns the conditions that stop execution as soon as they hold.
+    pub fn conditions(&self) -> &[Condition] {
+        &self.conditions
+    }
+
+    pub fn add_condition(&mut self, cond: Condition) {
+        if !self.conditions.contains(&cond) {
+            self.conditions.push(cond);
+        }
+    }
+
+    pub fn remove_condition(&mut self, cond: Condition) {
+        self.conditions.retain(|c| *c != cond);
+    }
+
+    pub fn add_watchpoint(&mut self, addr: u16, access: Access) {
+        self.gb.cpu_mut().add_watchpoint(addr, access);
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: u16, access: Access) {
+        self.gb.cpu_mut().remove_watchpoint(addr, access);
+    }
+
+    /// Returns the ROM bank mapped at `addr`.
+    pub fn bank(&self, addr: u16) -> usize {
+        match addr {
+            0x0000..=0x3fff => 0,
+            0x4000..=0x7fff => self.gb.mmu().cartridge().rom_bank(),
+            _ => 0,
+        }
+    }
+
+    /// Decodes the instruction at `addr` as currently mapped.
+    pub fn decode(&self, addr: u16) -> Instruction {
+        Instruction::decode(self.gb.mmu(), self.bank(addr), addr)
+    }
+
+    /// Executes a single instruction, entering calls.
+    pub fn step_into(&mut self) -> Stop {
+        self.run_until(Until::Steps(1))
+    }
+
+    /// Executes a single instruction. Calls (CALL and RST) are run until they return.
+    pub fn step_over(&mut self) -> Stop {
+        let instr = self.decode(self.gb.cpu().reg().pc);
+        if instr.is_call() {
+            let sp = self.gb.cpu().reg().sp;
+            self.run_until(Until::Return(instr.next(), sp))
+        } else {
+            self.step_into()
+        }
+    }
+
+    /// Runs until the current function returns.
+    pub fn step_out(&mut self) -> Stop {
+        let sp = self.gb.cpu().reg().sp;
+        self.run_until(Until::Out(sp))
+    }
+
+    /// Runs until the given event, giving up with [`Stop::Step`] after `steps` instructions
+    /// (the event may never come, e.g. with interrupts disabled). Waiting for VBlank stops with
+    /// [`Stop::LcdOff`] as soon as the LCD is off.
+    pub fn run_until_event(&mut self, event: Event, steps: u64) -> Stop {
+        self.run_until(Until::Event(event, steps))
+    }
+
+    /// Runs until a breakpoint, watchpoint or condition triggers.
+    pub fn cont(&mut self) -> Stop {
+        self.run_until(Until::Never)
+    }
+
+    /// Like [`cont`](Self::cont), but gives up with [`Stop::Step`] after `steps` instructions.
+    /// Useful to poll for user input while running.
+    pub fn cont_for(&mut self, steps: u64) -> Stop {
+        self.run_until(Until::Budget(steps))
+    }
+
+    fn run_until(&mut self, until: Until) -> Stop {
+        let mut steps = 0;
+        loop {
+            let lcd_off = self.gb.mmu().read(0xff40) & 0x80 == 0;
+            if lcd_off && matches!(until, Until::Event(Event::VBlank, _)) {
+                return Stop::LcdOff;
+            }
+            let pc = self.gb.cpu().reg().pc;
+            let mnemonic = self.decode(pc).instr.mnemonic;
+            let ly = self.gb.mmu().read(0xff44);
+            let halted = self.gb.cpu().halt();
+
+            self.gb.step();
+            steps += 1;
+
+            let cpu = self.gb.cpu();
+            if let Some(opcode) = cpu.locked() {
+                return Stop::Locked(opcode);
+            }
+            if let Some(hit) = cpu.watch_hit() {
+                return Stop::Watchpoint(hit);
+            }
+            // An instruction was executed, rather than an interrupt dispatch or a halted cycle.
+            let executed = cpu.dispatched().is_none() && !halted;
+            let reg = cpu.reg();
+            match until {
+                Until::Steps(n) if steps >= n => return Stop::Step,
+                Until::Return(addr, sp) if reg.pc == addr && reg.sp >= sp => return Stop::Step,
+                Until::Out(sp)
+                    if executed
+                       && matches!(mnemonic, Mnemonic::Ret | Mnemonic::Reti)
+                       && reg.sp > sp =>
+                {
+                    return Stop::Step
+                }
+                Until::Event(Event::VBlank, _) if ly != 144 && self.gb.mmu().read(0xff44) == 144 => {
+                    return Stop::Event(Event::VBlank)
+                }
+                Until::Event(Event::Interrupt, _) if cpu.dispatched().is_some() => {
+                    return Stop::Event(Event::Interrupt)
+                }
+                _ => {}
+            }
+
+            if let Some(bp) = self.breakpoint() {
+                return Stop::Breakpoint(bp);
+            }
+            if let Some(&cond) = self.conditions.iter().find(|cond| cond.eval(&self.gb)) {
+                return Stop::Condition(cond);
+            }
+            if let Until::Budget(n) | Until::Event(_, n) = until {
+                if steps >= n {
+                    return Stop::Step;
+                }
+            }
+        }
+    }
+
+    // Returns the breakpoint at PC that triggers, if any.
+    fn breakpoint(&self) -> Option<Breakpoint> {
+        let pc = self.gb.cpu().reg().pc;
+        let bank = self.bank(pc);
+        self.breakpoints.iter().copied().find(|bp| {
+            bp.addr == pc
+            && (!(0x4000..0x8000).contains(&pc) || bp.bank.is_none_or(|b| b == bank))
+            && bp.cond.is_none_or(|cond| cond.eval(&self.gb))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cartridge::{self, cartridge::Cartridge},
+        Builder,
+    };
+
+    type Dbg = Debugger<Box<dyn Cartridge>, (), ()>;
+
+    // Debugger around an MBC1 ROM of 4 banks, with `code` at 0x0100 and a RET at the start of
+    // every bank.
+    fn debugger(code: &[u8]) -> Dbg {
+        let mut rom = vec![0; 0x10000];
+        for bank in rom.chunks_mut(0x4000) {
+            bank[0] = 0xc9;
+        }
+        rom[0x147] = 0x01;
+        rom[0x148] = 0x01;
+        rom[0x100..0x100 + code.len()].copy_from_slice(code);
+        let cartridge = cartridge::from_bytes(&rom).unwrap();
+        Debugger::new(Builder::default().cartridge(cartridge).gb_mode().skip_boot().build())
+    }
+
+    #[test]
+    fn parse_condition() {
+        let cond = |reg, cmp, value| Ok(Condition { reg, cmp, value });
+        assert_eq!("a==$3f".parse(), cond(Reg::A, Cmp::Eq, 0x3f));
+        assert_eq!("HL != 0xC000".parse(), cond(Reg::HL, Cmp::Ne, 0xc000));
+        assert_eq!("sp<=fffe".parse(), cond(Reg::SP, Cmp::Le, 0xfffe));
+        assert_eq!("bc>=$10".parse(), cond(Reg::BC, Cmp::Ge, 0x10));
+        assert_eq!("pc<4000".parse(), cond(Reg::PC, Cmp::Lt, 0x4000));
+        assert_eq!("f>0x80".parse(), cond(Reg::F, Cmp::Gt, 0x80));
+        for s in ["a=1", "a==", "x==1", "a==$g", "a==$10000", "==1"] {
+            assert_eq!(s.parse::<Condition>(), Err(()), "{}", s);
+        }
+    }
+
+    #[test]
+    fn bank_breakpoints() {
+        #[rustfmt::skip]
+        let mut dbg = debugger(&[
+            0x3e, 0x02,       // $0100: LD A,$02
+            0xea, 0x00, 0x20, // $0102: LD ($2000),A
+            0xcd, 0x00, 0x40, // $0105: CALL $4000
+            0x3c,             // $0108: INC A
+            0xea, 0x00, 0x20, // $0109: LD ($2000),A
+            0xcd, 0x00, 0x40, // $010C: CALL $4000
+            0x18, 0xfe,       // $010F: JR $010F
+        ]);
+        dbg.add_breakpoint(Breakpoint { bank: Some(3), addr: 0x4000, cond: None });
+        // the bank is ignored outside of the switchable bank area
+        dbg.add_breakpoint(Breakpoint { bank: Some(3), addr: 0x0108, cond: None });
+        assert!(matches!(dbg.cont(), Stop::Breakpoint(Breakpoint { addr: 0x0108, .. })));
+        assert!(matches!(dbg.cont(), Stop::Breakpoint(Breakpoint { addr: 0x4000, .. })));
+        assert_eq!(dbg.bank(0x4000), 3);
+
+        dbg.remove_bank_breakpoint(Some(3), 0x4000);
+        dbg.add_breakpoint(Breakpoint { bank: Some(2), addr: 0x4000, cond: None });
+        assert_eq!(dbg.cont_for(1000), Stop::Step);
+        assert_eq!(dbg.gb().cpu().reg().pc, 0x010f);
+    }
+
+    #[test]
+    fn step_over_and_out() {
+        let mut code = vec![0; 0x120];
+        #[rustfmt::skip]
+        let main = [
+            0xcd, 0x00, 0x02, // $0100: CALL $0200
+            0xc7,             // $0103: RST $00
+            0xcd, 0x10, 0x02, // $0104: CALL $0210
+            0x18, 0xfe,       // $0107: JR $0107
+        ];
+        code[..main.len()].copy_from_slice(&main);
+        // $0200: NOP, RET
+        code[0x100..0x102].copy_from_slice(&[0x00, 0xc9]);
+        // $0210: NOP, CALL $0200, NOP, RET
+        code[0x110..0x116].copy_from_slice(&[0x00, 0xcd, 0x00, 0x02, 0x00, 0xc9]);
+        let mut dbg = debugger(&code);
+        let pc = |dbg: &Dbg| dbg.gb().cpu().reg().pc;
+
+        assert_eq!(dbg.step_over(), Stop::Step);
+        assert_eq!(pc(&dbg), 0x0103);
+        assert_eq!(dbg.step_over(), Stop::Step);
+        assert_eq!(pc(&dbg), 0x0104);
+        assert_eq!(dbg.step_into(), Stop::Step);
+        assert_eq!(pc(&dbg), 0x0210);
+        assert_eq!(dbg.step_over(), Stop::Step);
+        assert_eq!(pc(&dbg), 0x0211);
+        // the nested call returns first
+        assert_eq!(dbg.step_out(), Stop::Step);
+        assert_eq!(pc(&dbg), 0x0107);
+
+        // breakpoints inside a call stop a step over
+        dbg.gb_mut().cpu_mut().reg_mut().pc = 0x0100;
+        dbg.add_breakpoint(Breakpoint { bank: None, addr: 0x0201, cond: None });
+        assert!(matches!(dbg.step_over(), Stop::Breakpoint(Breakpoint { addr: 0x0201, .. })));
+    }
+
+    #[test]
+    fn vblank_with_lcd_off() {
+        #[rustfmt::skip]
+        let mut dbg = debugger(&[
+            0xf3,       // DI
+            0x18, 0xfe, // JR $0101
+        ]);
+        assert_eq!(dbg.run_until_event(Event::VBlank, 100_000), Stop::Event(Event::VBlank));
+        assert_eq!(dbg.gb().mmu().read(0xff44), 144);
+        // interrupts are disabled
+        assert_eq!(dbg.run_until_event(Event::Interrupt, 1000), Stop::Step);
+        // already off
+        dbg.gb_mut().mmu_mut().write(0xff40, 0x00);
+        let pc = dbg.gb().cpu().reg().pc;
+        assert_eq!(dbg.run_until_event(Event::VBlank, 100_000), Stop::LcdOff);
+        assert_eq!(dbg.gb().cpu().reg().pc, pc);
+
+        // turned off while waiting
+        #[rustfmt::skip]
+        let mut dbg = debugger(&[
+            0xaf,       // XOR A
+            0xe0, 0x40, // LDH ($40),A
+            0x18, 0xfe, // JR $0103
+        ]);
+        assert_eq!(dbg.run_until_event(Event::VBlank, 100_000), Stop::LcdOff);
+        assert_eq!(dbg.gb().cpu().reg().pc, 0x0103);
+    }
+}
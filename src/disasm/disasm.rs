@@ -0,0 +1,338 @@
+use crate::{
+    cartridge::cartridge::Cartridge,
+    cpu::cpu::opcode::{self, Instr, Mnemonic, Operand},
+    device::device::Device,
+};
+use super::symbols::Symbols;
+use std::{
+    collections::BTreeSet,
+    fmt,
+    io::{self, Write},
+};
+
+/// Size of a ROM bank.
+pub const BANK_SIZE: usize = 0x4000;
+
+// Maximum number of data bytes per `db` line of a listing.
+const DATA_PER_LINE: usize = 8;
+
+/// Addresses where execution may begin without a jump from other code: the cartridge entry
+/// point, the RST vectors and the interrupt vectors.
+pub const ENTRY_POINTS: [u16; 14] = [0x0100, 0x0000, 0x0008, 0x0010, 0x0018, 0x0020, 0x0028,
+                                     0x0030, 0x0038, 0x0040, 0x0048, 0x0050, 0x0058, 0x0060];
+
+/// An instruction decoded at a given bank and address.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Instruction {
+    pub bank: usize,
+    pub addr: u16,
+    pub instr: &'static Instr,
+    // Only the first `instr.len` bytes are meaningful.
+    bytes: [u8; 3],
+}
+
+impl Instruction {
+    /// Decodes the instruction at `addr`, reading its bytes from `device`. `bank` is only
+    /// used to annotate the result.
+    pub fn decode<D: Device + ?Sized>(device: &D, bank: usize, addr: u16) -> Self {
+        let read = |i: u16| device.read(addr.wrapping_add(i));
+        Self::new(bank, addr, [read(0), read(1), read(2)])
+    }
+
+    fn new(bank: usize, addr: u16, bytes: [u8; 3]) -> Self {
+        Self { bank,
+               addr,
+               instr: opcode::decode(bytes[0], bytes[1]),
+               bytes }
+    }
+
+    /// Returns the encoded instruction.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.instr.len as usize]
+    }
+
+    /// Returns the address of the instruction that follows this one.
+    pub fn next(&self) -> u16 {
+        self.addr.wrapping_add(u16::from(self.instr.len))
+    }
+
+    /// Returns the address this instruction may jump to, if it is known statically.
+    /// `JP HL` and returns have no static target.
+    pub fn target(&self) -> Option<u16> {
+        match (self.instr.mnemonic, self.instr.operands) {
+            (Mnemonic::Jr, _) => Some(self.next().wrapping_add(i16::from(self.imm8() as i8) as u16)),
+            (Mnemonic::Jp, [_, Some(Operand::A16)])
+            | (Mnemonic::Jp, [Some(Operand::A16), None])
+            | (Mnemonic::Call, _) => Some(self.imm16()),
+            (Mnemonic::Rst, [Some(Operand::Vec(vec)), _]) => Some(u16::from(vec)),
+            _ => None,
+        }
+    }
+
+    /// Returns true if this is a call (CALL or RST), i.e. execution may return to the
+    /// next instruction once the target returns.
+    pub fn is_call(&self) -> bool {
+        matches!(self.instr.mnemonic, Mnemonic::Call | Mnemonic::Rst)
+    }
+
+    /// Returns true if execution may continue with the next instruction.
+    pub fn falls_through(&self) -> bool {
+        match self.instr.mnemonic {
+            Mnemonic::Jr | Mnemonic::Jp | Mnemonic::Ret => self.instr.cond().is_some(),
+            Mnemonic::Reti | Mnemonic::Illegal => false,
+            _ => true,
+        }
+    }
+
+    fn imm8(&self) -> u8 {
+        self.bytes[1]
+    }
+
+    fn imm16(&self) -> u16 {
+        u16::from_le_bytes([self.bytes[1], self.bytes[2]])
+    }
+
+    /// Returns a value that formats the instruction like its `Display` implementation, but with
+    /// addresses replaced by the symbols defined at them.
+    pub fn display<'a>(&'a self, symbols: &'a Symbols) -> impl fmt::Display + 'a {
+        WithSymbols { instr: self, symbols: Some(symbols) }
+    }
+
+    fn fmt_addr(&self, f: &mut fmt::Formatter<'_>, symbols: Option<&Symbols>, addr: u16) -> fmt::Result {
+        match symbols.and_then(|symbols| symbols.get(self.bank.max(1), addr)) {
+            Some(name) => f.write_str(name),
+            None => write!(f, "${:04X}", addr),
+        }
+    }
+
+    fn fmt_operand(&self,
+                   f: &mut fmt::Formatter<'_>,
+                   symbols: Option<&Symbols>,
+                   op: Operand)
+                   -> fmt::Result {
+        let signed = |f: &mut fmt::Formatter<'_>| {
+            let r8 = self.imm8() as i8;
+            let sign = if r8 < 0 { '-' } else { '+' };
+            write!(f, "{}${:02X}", sign, r8.unsigned_abs())
+        };
+        match op {
+            Operand::D8 => write!(f, "${:02X}", self.imm8()),
+            Operand::D16 => write!(f, "${:04X}", self.imm16()),
+            Operand::IndA8 => {
+                f.write_str("(")?;
+                self.fmt_addr(f, symbols, 0xff00 | u16::from(self.imm8()))?;
+                f.write_str(")")
+            }
+            Operand::A16 => self.fmt_addr(f, symbols, self.imm16()),
+            Operand::IndA16 => {
+                f.write_str("(")?;
+                self.fmt_addr(f, symbols, self.imm16())?;
+                f.write_str(")")
+            }
+            Operand::R8 if self.instr.mnemonic == Mnemonic::Jr => {
+                self.fmt_addr(f, symbols, self.target().unwrap())
+            }
+            Operand::R8 => signed(f),
+            Operand::SPR8 => {
+                f.write_str("SP")?;
+                signed(f)
+            }
+            _ => write!(f, "{}", op),
+        }
+    }
+}
+
+/// Formats the instruction with its immediates and jump targets resolved
+/// (e.g. `LD A,($C000)` or `JR NZ,$0150`).
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        WithSymbols { instr: self, symbols: None }.fmt(f)
+    }
+}
+
+struct WithSymbols<'a> {
+    instr: &'a Instruction,
+    symbols: Option<&'a Symbols>,
+}
+
+impl fmt::Display for WithSymbols<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.instr.instr.mnemonic)?;
+        for (i, op) in self.instr.instr.operands().enumerate() {
+            f.write_str(if i == 0 { " " } else { "," })?;
+            self.instr.fmt_operand(f, self.symbols, op)?;
+        }
+        Ok(())
+    }
+}
+
+/// Disassembler over a raw ROM image.
+///
+/// Addresses are given as `bank:addr` pairs, where `addr` is a CPU address in the
+/// 0x0000-0x7fff range. Addresses below 0x4000 always refer to bank 0.
+pub struct Disassembler<'a> {
+    rom: &'a [u8],
+}
+
+impl<'a> Disassembler<'a> {
+    pub fn new(rom: &'a [u8]) -> Self {
+        Self { rom }
+    }
+
+    pub fn from_cartridge<C: Cartridge + ?Sized>(cartridge: &'a C) -> Self {
+        Self::new(cartridge.rom())
+    }
+
+    /// Returns the number of ROM banks.
+    pub fn banks(&self) -> usize {
+        self.rom.len().div_ceil(BANK_SIZE)
+    }
+
+    /// Returns the offset of `bank:addr` into the ROM image, or `None` if the address lies
+    /// outside of the ROM.
+    pub fn offset(&self, bank: usize, addr: u16) -> Option<usize> {
+        let offset = match addr {
+            0x0000..=0x3fff => addr as usize,
+            0x4000..=0x7fff => bank * BANK_SIZE + addr as usize - BANK_SIZE,
+            _ => return None,
+        };
+        (offset < self.rom.len()).then_some(offset)
+    }
+
+    /// Returns the byte at `bank:addr`. Bytes outside of the ROM read as 0xff.
+    pub fn read(&self, bank: usize, addr: u16) -> u8 {
+        self.offset(bank, addr).map(|offset| self.rom[offset]).unwrap_or(0xff)
+    }
+
+    /// Decodes the instruction at `bank:addr`.
+    pub fn decode(&self, bank: usize, addr: u16) -> Instruction {
+        // The last bytes of an instruction at the end of bank 0 come from the switchable bank.
+        let (bank, next) = if addr < 0x4000 { (0, bank.max(1)) } else { (bank, bank) };
+        let read = |i: u16| self.read(next, addr.wrapping_add(i));
+        Instruction::new(bank, addr, [read(0), read(1), read(2)])
+    }
+
+    /// Follows the control flow from the given entry points and returns the ROM bytes that
+    /// were reached as code.
+    ///
+    /// Bank switches are not tracked: jumps into 0x4000-0x7fff from bank 0 are assumed to land
+    /// in bank 1, and jumps from a switchable bank stay in that bank. Code outside of the ROM
+    /// (e.g. copied to RAM) is not followed.
+    pub fn trace(&self, entries: &[(usize, u16)]) -> Trace {
+        let mut trace = Trace { starts: BTreeSet::new(),
+                                labels: BTreeSet::new(),
+                                code: vec![false; self.rom.len()] };
+        let mut pending: Vec<(usize, u16)> = entries.to_vec();
+
+        while let Some((bank, mut addr)) = pending.pop() {
+            // The bank mapped at 0x4000-0x7fff while following this path.
+            let bank = bank.max(1);
+            loop {
+                let instr = self.decode(bank, addr);
+                let offset = match self.offset(instr.bank, addr) {
+                    Some(offset) if !trace.starts.contains(&offset) => offset,
+                    _ => break,
+                };
+                trace.starts.insert(offset);
+                for i in 0..instr.instr.len as usize {
+                    if let Some(code) = trace.code.get_mut(offset + i) {
+                        *code = true;
+                    }
+                }
+
+                if let Some(target) = instr.target() {
+                    if let Some(offset) = self.offset(bank, target) {
+                        trace.labels.insert(offset);
+                        pending.push((bank, target));
+                    }
+                }
+                if !instr.falls_through() || instr.next() >= 0x8000 {
+                    break;
+                }
+                addr = instr.next();
+            }
+        }
+        trace
+    }
+
+    /// Writes a listing of every bank, with the instructions found by `trace` and the rest of
+    /// the ROM as `db` lines. Labels come from `symbols`, or are generated for jump and call
+    /// targets.
+    pub fn dump<W: Write>(&self, out: &mut W, trace: &Trace, symbols: &Symbols) -> io::Result<()> {
+        for bank in 0..self.banks() {
+            let base: u16 = if bank == 0 { 0x0000 } else { 0x4000 };
+            writeln!(out, "; ROM bank ${:02X}", bank)?;
+
+            let end = base + BANK_SIZE as u16;
+            let mut addr = base;
+            while let Some(offset) = self.offset(bank, addr).filter(|_| addr < end) {
+                let label = symbols.get(bank, addr);
+                if let Some(label) = label {
+                    writeln!(out, "{}:", label)?;
+                } else if trace.is_label(offset) {
+                    writeln!(out, "L{:02X}_{:04X}:", bank, addr)?;
+                }
+
+                if trace.is_instruction(offset) {
+                    let instr = self.decode(bank, addr);
+                    let bytes: Vec<String> = instr.bytes()
+                                                  .iter()
+                                                  .map(|b| format!("{:02X}", b))
+                                                  .collect();
+                    writeln!(out,
+                             "{:02X}:{:04X}  {:<8}  {}",
+                             bank,
+                             addr,
+                             bytes.join(" "),
+                             instr.display(symbols))?;
+                    addr = addr.saturating_add(u16::from(instr.instr.len));
+                    continue;
+                }
+
+                // Data runs end at the next instruction or label.
+                let mut data = vec![format!("${:02X}", self.read(bank, addr))];
+                while data.len() < DATA_PER_LINE {
+                    let next = addr + data.len() as u16;
+                    match self.offset(bank, next) {
+                        Some(offset) if next < end
+                                        && !trace.is_instruction(offset)
+                                        && !trace.is_label(offset)
+                                        && symbols.get(bank, next).is_none() =>
+                        {
+                            data.push(format!("${:02X}", self.read(bank, next)))
+                        }
+                        _ => break,
+                    }
+                }
+                writeln!(out, "{:02X}:{:04X}  db {}", bank, addr, data.join(","))?;
+                addr += data.len() as u16;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+/// Result of [`Disassembler::trace`]. All offsets are into the ROM image.
+pub struct Trace {
+    starts: BTreeSet<usize>,
+    labels: BTreeSet<usize>,
+    code: Vec<bool>,
+}
+
+impl Trace {
+    /// Returns true if an instruction begins at `offset`.
+    pub fn is_instruction(&self, offset: usize) -> bool {
+        self.starts.contains(&offset)
+    }
+
+    /// Returns true if the byte at `offset` belongs to an instruction.
+    pub fn is_code(&self, offset: usize) -> bool {
+        self.code.get(offset).copied().unwrap_or(false)
+    }
+
+    /// Returns true if `offset` is the target of a jump or call.
+    pub fn is_label(&self, offset: usize) -> bool {
+        self.labels.contains(&offset)
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::Path,
+};
+
+/// Symbol table, as loaded from the `.sym` files emitted by RGBDS (`rgblink -n`).
+///
+/// Each line of a symbol file is `bank:addr label`, in hex, and comments start with `;`:
+///
+/// ```text
+/// ; File generated by rgblink
+/// 00:0150 Main
+/// 01:4000 Intro.loop
+/// ```
+///
+/// The bank is only meaningful for the switchable ROM area (0x4000-0x7fff). Symbols at other
+/// addresses are looked up regardless of their bank.
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    by_addr: BTreeMap<(usize, u16), String>,
+    by_name: HashMap<String, (usize, u16)>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads a symbol file.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parses the contents of a symbol file.
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut symbols = Self::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || io::Error::new(io::ErrorKind::InvalidData,
+                                            format!("line {}: expected `bank:addr label`", i + 1));
+            let (loc, name) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            let (bank, addr) = loc.split_once(':').ok_or_else(invalid)?;
+            let bank = usize::from_str_radix(bank, 16).map_err(|_| invalid())?;
+            let addr = u16::from_str_radix(addr, 16).map_err(|_| invalid())?;
+            symbols.insert(bank, addr, name.trim());
+        }
+        Ok(symbols)
+    }
+
+    pub fn insert(&mut self, bank: usize, addr: u16, name: &str) {
+        let key = key(bank, addr);
+        self.by_addr.entry(key).or_insert_with(|| name.to_string());
+        self.by_name.insert(name.to_string(), key);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_addr.is_empty()
+    }
+
+    /// Returns the symbol at `addr`, where `bank` is the ROM bank mapped at 0x4000-0x7fff.
+    pub fn get(&self, bank: usize, addr: u16) -> Option<&str> {
+        self.by_addr.get(&key(bank, addr)).map(String::as_str)
+    }
+
+    /// Returns the closest symbol at or below `addr` in the same bank, and the offset of
+    /// `addr` from it.
+    pub fn nearest(&self, bank: usize, addr: u16) -> Option<(&str, u16)> {
+        let (bank, _) = key(bank, addr);
+        self.by_addr
+            .range(..=(bank, addr))
+            .next_back()
+            // Don't cross from ROM into RAM.
+            .filter(|((b, a), _)| *b == bank && (*a < 0x8000) == (addr < 0x8000))
+            .map(|((_, a), name)| (name.as_str(), addr - a))
+    }
+
+    /// Returns the bank and address of the symbol called `name`.
+    pub fn lookup(&self, name: &str) -> Option<(usize, u16)> {
+        self.by_name.get(name).copied()
+    }
+
+    /// Formats `addr` as `label` or `label+offset` when there is a symbol at most `max_offset`
+    /// bytes below it, or as `$XXXX` otherwise.
+    pub fn format(&self, bank: usize, addr: u16, max_offset: u16) -> String {
+        match self.nearest(bank, addr) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) if offset <= max_offset => format!("{}+{}", name, offset),
+            _ => format!("${:04X}", addr),
+        }
+    }
+}
+
+// Symbols outside of the switchable ROM area are stored in bank 0.
+fn key(bank: usize, addr: u16) -> (usize, u16) {
+    match addr {
+        0x4000..=0x7fff => (bank, addr),
+        _ => (0, addr),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYM: &str = "\
+; File generated by rgblink
+
+00:0150 Main
+00:0158 Main.loop ; local label
+01:4000 Intro
+01:4003 Intro.wait
+02:4000\tOther
+00:C000 wBuffer
+";
+
+    #[test]
+    fn parse() {
+        let symbols = Symbols::parse(SYM).unwrap();
+        assert_eq!(symbols.lookup("Main"), Some((0, 0x0150)));
+        assert_eq!(symbols.lookup("Main.loop"), Some((0, 0x0158)));
+        assert_eq!(symbols.lookup("Intro.wait"), Some((1, 0x4003)));
+        assert_eq!(symbols.lookup("Other"), Some((2, 0x4000)));
+        assert_eq!(symbols.lookup("wBuffer"), Some((0, 0xc000)));
+        assert_eq!(symbols.lookup("loop"), None);
+
+        let err = Symbols::parse("00:0150 Main\n00:zz Oops\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("line 2:"), "{}", err);
+        assert!(Symbols::parse("Main").is_err());
+        assert!(Symbols::parse("; nothing\n\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn banks() {
+        let symbols = Symbols::parse(SYM).unwrap();
+        // the bank only matters in the switchable ROM area
+        assert_eq!(symbols.get(1, 0x4000), Some("Intro"));
+        assert_eq!(symbols.get(2, 0x4000), Some("Other"));
+        assert_eq!(symbols.get(3, 0x4000), None);
+        assert_eq!(symbols.get(5, 0x0150), Some("Main"));
+        assert_eq!(symbols.get(5, 0xc000), Some("wBuffer"));
+
+        assert_eq!(symbols.format(1, 0x4005, 16), "Intro.wait+2");
+        assert_eq!(symbols.format(2, 0x4005, 16), "Other+5");
+        assert_eq!(symbols.format(3, 0x4005, 16), "$4005");
+        assert_eq!(symbols.format(2, 0x4005, 4), "$4005");
+        assert_eq!(symbols.format(0, 0x015a, 16), "Main.loop+2");
+        // no symbol crosses from ROM into RAM
+        assert_eq!(symbols.format(0, 0x8000, u16::MAX), "$8000");
+    }
+}
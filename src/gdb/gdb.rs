@@ -0,0 +1,537 @@
+//! GDB remote serial protocol (RSP) stub.
+//!
+//! Exposes the SM83 core to GDB (or any RSP front-end) over TCP. The register set is
+//! described by [`TARGET_XML`]: AF, BC, DE, HL, SP and PC, 16 bits each, in that order.
+//!
+//! Addresses are 16-bit CPU addresses. Breakpoints may be restricted to a ROM bank by
+//! putting the bank number in bits 16 and up (e.g. `0x34000` is bank 3, address 0x4000).
+use crate::{
+    apu::device::Audio,
+    cartridge::cartridge::Cartridge,
+    cpu::cpu::Access,
+    debugger::debugger::{Breakpoint, Debugger, Stop},
+    device::device::Device,
+    ppu::ppu::Video,
+};
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+/// Target description of the SM83 register set.
+pub const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.gnu.gdb.sm83.core">
+    <reg name="af" bitsize="16" type="uint16" regnum="0"/>
+    <reg name="bc" bitsize="16" type="uint16"/>
+    <reg name="de" bitsize="16" type="uint16"/>
+    <reg name="hl" bitsize="16" type="uint16"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+// Number of registers in the target description.
+const REGS: usize = 6;
+
+// Instructions executed between checks for an interrupt request from the client.
+const POLL_STEPS: u64 = 10_000;
+
+// SIGINT and SIGTRAP, as reported in stop replies.
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+/// Byte stream to a client.
+pub trait Connection: Read + Write {
+    /// Reads a byte if one was received, without blocking. Used to check for interrupt requests
+    /// while the target is running.
+    fn poll(&mut self) -> io::Result<Option<u8>>;
+}
+
+impl Connection for TcpStream {
+    fn poll(&mut self) -> io::Result<Option<u8>> {
+        self.set_nonblocking(true)?;
+        let mut byte = [0];
+        let read = self.read(&mut byte);
+        self.set_nonblocking(false)?;
+        match read {
+            Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+            Ok(_) => Ok(Some(byte[0])),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Waits for a client on `addr` and serves it until it detaches, kills the target or
+/// disconnects.
+pub fn serve<C: Cartridge, V: Video, D: Audio, A: ToSocketAddrs>(dbg: &mut Debugger<C, V, D>,
+                                                                 addr: A)
+                                                                 -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    serve_connection(dbg, stream)
+}
+
+/// Serves a client already connected through `stream`, like [`serve`].
+pub fn serve_connection<C, V, D, S>(dbg: &mut Debugger<C, V, D>, stream: S) -> io::Result<()>
+    where C: Cartridge,
+          V: Video,
+          D: Audio,
+          S: Connection
+{
+    GdbStub { dbg, stream }.run()
+}
+
+struct GdbStub<'a, C: Cartridge, V: Video, D: Audio, S: Connection> {
+    dbg: &'a mut Debugger<C, V, D>,
+    stream: S,
+}
+
+impl<C: Cartridge, V: Video, D: Audio, S: Connection> GdbStub<'_, C, V, D, S> {
+    fn run(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.recv()? {
+            // `c` and `s` may give the address to resume at.
+            if let Some(addr) = packet.strip_prefix(['c', 's'])
+                                      .and_then(|addr| u16::from_str_radix(addr, 16).ok())
+            {
+                self.set_reg(5, addr);
+            }
+            let reply = match packet.as_bytes().first() {
+                Some(b'c') => self.cont()?,
+                Some(b's') => {
+                    let stop = self.dbg.step_into();
+                    stop_reply(stop)
+                }
+                Some(b'D') => {
+                    self.send("OK")?;
+                    return Ok(());
+                }
+                Some(b'k') => return Ok(()),
+                _ => self.command(&packet).unwrap_or_else(|| "E01".to_string()),
+            };
+            self.send(&reply)?;
+        }
+        Ok(())
+    }
+
+    // Handles the packets that don't resume execution. Returns `None` for malformed packets.
+    fn command(&mut self, packet: &str) -> Option<String> {
+        if packet.is_empty() {
+            return Some(String::new());
+        }
+        let (cmd, args) = (packet.get(..1)?, &packet[1..]);
+        let reply = match cmd {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => (0..REGS).map(|i| hex_u16(self.reg(i))).collect(),
+            "G" => {
+                for i in 0..REGS {
+                    let value = parse_u16_le(args.get(i * 4..i * 4 + 4)?)?;
+                    self.set_reg(i, value);
+                }
+                "OK".to_string()
+            }
+            "p" => hex_u16(self.reg(usize::from_str_radix(args, 16).ok().filter(|&i| i < REGS)?)),
+            "P" => {
+                let (reg, value) = args.split_once('=')?;
+                let reg = usize::from_str_radix(reg, 16).ok().filter(|&i| i < REGS)?;
+                self.set_reg(reg, parse_u16_le(value)?);
+                "OK".to_string()
+            }
+            "m" => {
+                let (addr, len) = args.split_once(',')?;
+                let addr = u16::from_str_radix(addr, 16).ok()?;
+                let len = u16::from_str_radix(len, 16).ok()?;
+                let mmu = self.dbg.gb().mmu();
+                (0..len).map(|i| format!("{:02x}", mmu.read(addr.wrapping_add(i)))).collect()
+            }
+            "M" => {
+                let (addr, data) = args.split_once(':')?;
+                let (addr, _) = addr.split_once(',')?;
+                let addr = u16::from_str_radix(addr, 16).ok()?;
+                let data = parse_hex(data)?;
+                let mmu = self.dbg.gb_mut().mmu_mut();
+                for (i, byte) in data.into_iter().enumerate() {
+                    mmu.write(addr.wrapping_add(i as u16), byte);
+                }
+                "OK".to_string()
+            }
+            "Z" | "z" => {
+                let mut fields = args.split(',');
+                let kind = fields.next()?;
+                let addr = u32::from_str_radix(fields.next()?, 16).ok()?;
+                self.breakpoint(cmd == "Z", kind, addr)?
+            }
+            "H" => "OK".to_string(),
+            "q" => self.query(args)?,
+            // Unsupported packets get an empty reply.
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    fn query(&self, query: &str) -> Option<String> {
+        if let Some(args) = query.strip_prefix("Xfer:features:read:target.xml:") {
+            let (offset, len) = args.split_once(',')?;
+            let offset = usize::from_str_radix(offset, 16).ok()?;
+            let len = usize::from_str_radix(len, 16).ok()?;
+            let data = TARGET_XML.get(offset.min(TARGET_XML.len())..)?;
+            return Some(if data.len() > len {
+                format!("m{}", &data[..len])
+            } else {
+                format!("l{}", data)
+            });
+        }
+        let reply = match query.split(':').next()? {
+            "Supported" => "PacketSize=4000;qXfer:features:read+",
+            "Attached" => "1",
+            "C" => "QC1",
+            "fThreadInfo" => "m1",
+            "sThreadInfo" => "l",
+            _ => "",
+        };
+        Some(reply.to_string())
+    }
+
+    // Inserts or removes a breakpoint (kind 0 and 1) or watchpoint (kinds 2 to 4).
+    fn breakpoint(&mut self, insert: bool, kind: &str, addr: u32) -> Option<String> {
+        let bank = match addr >> 16 {
+            0 => None,
+            bank => Some(bank as usize),
+        };
+        let addr = addr as u16;
+        let access: &[Access] = match kind {
+            "0" | "1" => {
+                if insert {
+                    self.dbg.add_breakpoint(Breakpoint { bank, addr, cond: None });
+                } else {
+                    self.dbg.remove_bank_breakpoint(bank, addr);
+                }
+                return Some("OK".to_string());
+            }
+            "2" => &[Access::Write],
+            "3" => &[Access::Read],
+            "4" => &[Access::Read, Access::Write],
+            _ => return Some(String::new()),
+        };
+        for &access in access {
+            if insert {
+                self.dbg.add_watchpoint(addr, access);
+            } else {
+                self.dbg.remove_watchpoint(addr, access);
+            }
+        }
+        Some("OK".to_string())
+    }
+
+    fn reg(&self, i: usize) -> u16 {
+        let reg = self.dbg.gb().cpu().reg();
+        [reg.af(), reg.bc(), reg.de(), reg.hl(), reg.sp, reg.pc][i]
+    }
+
+    fn set_reg(&mut self, i: usize, value: u16) {
+        let reg = self.dbg.gb_mut().cpu_mut().reg_mut();
+        match i {
+            0 => reg.set_af(value),
+            1 => reg.set_bc(value),
+            2 => reg.set_de(value),
+            3 => reg.set_hl(value),
+            4 => reg.sp = value,
+            _ => reg.pc = value,
+        }
+    }
+
+    // Runs until a stop, or until the client sends an interrupt request (^C).
+    fn cont(&mut self) -> io::Result<String> {
+        loop {
+            let stop = self.dbg.cont_for(POLL_STEPS);
+            if stop != Stop::Step {
+                return Ok(stop_reply(stop));
+            }
+            if self.stream.poll()? == Some(0x03) {
+                return Ok(format!("S{:02x}", SIGINT));
+            }
+        }
+    }
+
+    // Receives the next packet. Returns `None` if the client disconnected.
+    fn recv(&mut self) -> io::Result<Option<String>> {
+        let mut byte = [0];
+        // Skip acknowledgements and interrupt requests received while stopped.
+        loop {
+            if self.stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] == b'$' {
+                break;
+            }
+        }
+
+        // The checksum covers the packet as sent, escape characters included.
+        let mut packet = Vec::new();
+        let mut actual = 0u8;
+        loop {
+            if self.stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            match byte[0] {
+                b'#' => break,
+                b'}' => {
+                    self.stream.read_exact(&mut byte)?;
+                    actual = actual.wrapping_add(b'}').wrapping_add(byte[0]);
+                    packet.push(byte[0] ^ 0x20);
+                }
+                b => {
+                    actual = actual.wrapping_add(b);
+                    packet.push(b);
+                }
+            }
+        }
+        let mut checksum = [0; 2];
+        self.stream.read_exact(&mut checksum)?;
+
+        let expected = std::str::from_utf8(&checksum).ok()
+                                                     .and_then(|s| u8::from_str_radix(s, 16).ok());
+        if expected != Some(actual) {
+            self.stream.write_all(b"-")?;
+            return self.recv();
+        }
+        self.stream.write_all(b"+")?;
+        Ok(Some(String::from_utf8_lossy(&packet).into_owned()))
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        for &b in data.as_bytes() {
+            match b {
+                b'#' | b'$' | b'}' | b'*' => packet.extend([b'}', b ^ 0x20]),
+                b => packet.push(b),
+            }
+        }
+        let checksum = sum(&packet);
+        let mut frame = Vec::with_capacity(packet.len() + 4);
+        frame.push(b'$');
+        frame.extend(packet);
+        frame.extend(format!("#{:02x}", checksum).bytes());
+        self.stream.write_all(&frame)
+    }
+}
+
+fn stop_reply(stop: Stop) -> String {
+    match stop {
+        Stop::Watchpoint(hit) => {
+            let kind = match hit.access {
+                Access::Read => "rwatch",
+                Access::Write => "watch",
+            };
+            format!("T{:02x}{}:{:x};", SIGTRAP, kind, hit.addr)
+        }
+        _ => format!("S{:02x}", SIGTRAP),
+    }
+}
+
+fn sum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &b| sum.wrapping_add(b))
+}
+
+// Registers are sent in target byte order (little endian).
+fn hex_u16(value: u16) -> String {
+    format!("{:02x}{:02x}", value & 0xff, value >> 8)
+}
+
+fn parse_u16_le(s: &str) -> Option<u16> {
+    match parse_hex(s)?.as_slice() {
+        &[lo, hi] => Some(u16::from_le_bytes([lo, hi])),
+        _ => None,
+    }
+}
+
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len()).step_by(2)
+                .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+                .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GameBoy;
+    use std::io::Cursor;
+
+    type Dbg = Debugger<(), (), ()>;
+
+    // In-memory connection: reads from `input` and keeps everything written.
+    struct Pipe {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for Pipe {
+        fn poll(&mut self) -> io::Result<Option<u8>> {
+            let mut byte = [0];
+            Ok((self.input.read(&mut byte)? == 1).then_some(byte[0]))
+        }
+    }
+
+    fn stub<'a>(dbg: &'a mut Dbg, input: &str) -> GdbStub<'a, (), (), (), Pipe> {
+        GdbStub { dbg,
+                  stream: Pipe { input: Cursor::new(input.as_bytes().to_vec()),
+                                 output: Vec::new() } }
+    }
+
+    // Frames a packet that needs no escaping.
+    fn frame(packet: &str) -> String {
+        format!("${}#{:02x}", packet, sum(packet.as_bytes()))
+    }
+
+    #[test]
+    fn recv() {
+        let mut dbg = Debugger::new(GameBoy::default());
+        // acknowledgements and interrupt requests are skipped, escaped bytes are decoded and
+        // the checksum covers the escape characters
+        let input = format!("+\x03$a}}]b#{:02x}", sum(b"a}]b"));
+        let mut gdb = stub(&mut dbg, &input);
+        assert_eq!(gdb.recv().unwrap().as_deref(), Some("a}b"));
+        assert_eq!(gdb.stream.output, b"+");
+        assert_eq!(gdb.recv().unwrap(), None);
+
+        // bad checksums are rejected and the packet is expected again
+        let input = format!("$?#00{}", frame("?"));
+        let mut gdb = stub(&mut dbg, &input);
+        assert_eq!(gdb.recv().unwrap().as_deref(), Some("?"));
+        assert_eq!(gdb.stream.output, b"-+");
+    }
+
+    #[test]
+    fn send() {
+        let mut dbg = Debugger::new(GameBoy::default());
+        let mut gdb = stub(&mut dbg, "");
+        gdb.send("a}b#c$d*").unwrap();
+        let escaped = b"a}]b}\x03c}\x04d}\x0a";
+        let expected = format!("${}#{:02x}", String::from_utf8_lossy(escaped), sum(escaped));
+        assert_eq!(gdb.stream.output, expected.as_bytes());
+    }
+
+    #[test]
+    fn session() {
+        let mut dbg = Debugger::new(GameBoy::default());
+        let input = [frame("?"), frame("x"), frame("mzz,1"), frame("D")].concat();
+        let mut gdb = stub(&mut dbg, &input);
+        gdb.run().unwrap();
+        // unsupported packets get an empty reply, and malformed ones an error
+        let expected = ["S05", "", "E01", "OK"].map(|reply| format!("+{}", frame(reply))).concat();
+        assert_eq!(String::from_utf8_lossy(&gdb.stream.output), expected);
+    }
+
+    #[test]
+    fn interrupt() {
+        let mut dbg = Debugger::new(GameBoy::default());
+        let input = format!("{}\x03", frame("c"));
+        let mut gdb = stub(&mut dbg, &input);
+        assert_eq!(gdb.recv().unwrap().as_deref(), Some("c"));
+        assert_eq!(gdb.cont().unwrap(), "S02");
+    }
+
+    #[test]
+    fn registers() {
+        let mut dbg = Debugger::new(GameBoy::default());
+        let mut gdb = stub(&mut dbg, "");
+        let reg = gdb.dbg.gb_mut().cpu_mut().reg_mut();
+        reg.set_af(0x01b0);
+        reg.set_bc(0x0013);
+        reg.set_de(0x00d8);
+        reg.set_hl(0x014d);
+        reg.sp = 0xfffe;
+        reg.pc = 0x0100;
+        // little endian
+        assert_eq!(gdb.command("g").as_deref(), Some("b0011300d8004d01feff0001"));
+        assert_eq!(gdb.command("p5").as_deref(), Some("0001"));
+        assert_eq!(gdb.command("p6"), None);
+
+        assert_eq!(gdb.command("P1=3412").as_deref(), Some("OK"));
+        assert_eq!(gdb.dbg.gb().cpu().reg().bc(), 0x1234);
+        assert_eq!(gdb.command("P1=34"), None);
+
+        assert_eq!(gdb.command("G000011002200330044005500").as_deref(), Some("OK"));
+        let reg = gdb.dbg.gb().cpu().reg();
+        assert_eq!([reg.bc(), reg.de(), reg.hl(), reg.sp, reg.pc], [0x11, 0x22, 0x33, 0x44, 0x55]);
+        assert_eq!(gdb.command("G0000"), None);
+    }
+
+    #[test]
+    fn memory() {
+        let mut dbg = Debugger::new(GameBoy::default());
+        let mut gdb = stub(&mut dbg, "");
+        assert_eq!(gdb.command("Mc000,3:0102ff").as_deref(), Some("OK"));
+        assert_eq!(gdb.command("mc000,3").as_deref(), Some("0102ff"));
+        assert_eq!(gdb.command("mc001,0").as_deref(), Some(""));
+        assert_eq!(gdb.command("Mc000:01"), None);
+        assert_eq!(gdb.command("Mc000,1:1"), None);
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut dbg = Debugger::new(GameBoy::default());
+        let mut gdb = stub(&mut dbg, "");
+        let bp = |bank, addr| Breakpoint { bank, addr, cond: None };
+        // bank 3, address 0x4000
+        assert_eq!(gdb.command("Z0,34000,1").as_deref(), Some("OK"));
+        assert_eq!(gdb.command("Z1,4000,1").as_deref(), Some("OK"));
+        assert_eq!(gdb.dbg.breakpoints(), [bp(Some(3), 0x4000), bp(None, 0x4000)]);
+        assert_eq!(gdb.command("z0,34000,1").as_deref(), Some("OK"));
+        assert_eq!(gdb.dbg.breakpoints(), [bp(None, 0x4000)]);
+
+        assert_eq!(gdb.command("Z4,c000,1").as_deref(), Some("OK"));
+        let mut watchpoints = gdb.dbg.gb().cpu().watchpoints().collect::<Vec<_>>();
+        watchpoints.sort_by_key(|&(addr, access)| (addr, access == Access::Write));
+        assert_eq!(watchpoints, [(0xc000, Access::Read), (0xc000, Access::Write)]);
+        // unsupported kind
+        assert_eq!(gdb.command("Z5,c000,1").as_deref(), Some(""));
+    }
+
+    #[test]
+    fn target_xml() {
+        let mut dbg = Debugger::new(GameBoy::default());
+        let mut gdb = stub(&mut dbg, "");
+        let read = |gdb: &mut GdbStub<'_, (), (), (), Pipe>, offset: usize, len: usize| {
+            gdb.command(&format!("qXfer:features:read:target.xml:{:x},{:x}", offset, len)).unwrap()
+        };
+        let len = TARGET_XML.len();
+        assert_eq!(read(&mut gdb, 0, 0x10), format!("m{}", &TARGET_XML[..0x10]));
+        assert_eq!(read(&mut gdb, 0x10, 0x10), format!("m{}", &TARGET_XML[0x10..0x20]));
+        assert_eq!(read(&mut gdb, len - 4, 0x10), format!("l{}", &TARGET_XML[len - 4..]));
+        assert_eq!(read(&mut gdb, len, 0x10), "l");
+        // chunks put back together
+        let mut xml = String::new();
+        loop {
+            let chunk = read(&mut gdb, xml.len(), 0x40);
+            xml += &chunk[1..];
+            if chunk.starts_with('l') {
+                break;
+            }
+        }
+        assert_eq!(xml, TARGET_XML);
+    }
+}
@@ -37,6 +37,16 @@ impl Interrupts {
     pub fn is_active(&self, flag: Flag) -> bool {
         self.if_ & (flag as u8) != 0
     }
+
+    /// Returns the interrupts that are both requested and enabled.
+    pub fn pending(&self) -> u8 {
+        self.ie & self.if_ & 0x1f
+    }
+
+    /// Clears the request of the interrupt at bit `int` of the IF register.
+    pub fn acknowledge(&mut self, int: u8) {
+        self.if_ &= !(1 << int);
+    }
 }
 
 impl Device for Interrupts {
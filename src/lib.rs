@@ -2,7 +2,8 @@
             warn(dead_code, unused_imports, unused_variables))]
 #![deny(clippy::style, clippy::correctness, clippy::complexity, clippy::perf)]
 use crate::{
-    apu::device::Audio, cartridge::cartridge::Cartridge, cpu::cpu::Cpu, device::device::Device, mmu::mmu::Mmu, ppu::ppu::Video
+    apu::device::Audio, cartridge::cartridge::Cartridge, cpu::cpu::Cpu, device::device::Device,
+    mmu::mmu::Mmu, ppu::ppu::{Video, LINE, VBLANK},
 };
 use std::marker::PhantomData;
 
@@ -10,21 +11,30 @@ pub mod apu;
 pub mod cartridge;
 mod clock;
 pub mod cpu;
+pub mod debugger;
 pub mod device;
+pub mod disasm;
+pub mod gdb;
 pub mod interrupt;
 pub mod joypad;
 pub mod mmu;
 pub mod ppu;
+pub mod serial;
 pub mod timer;
 pub mod vram;
 pub mod wram;
+#[cfg(feature = "sdl")]
 pub mod sdlvideo;
+#[cfg(feature = "sdl")]
 pub mod callback;
 
 
 
 
-const CLOCK: u64 = 4_194_304;
+/// Frequency of the system clock, in Hz. Double speed mode doesn't affect it.
+pub const CLOCK: u64 = 4_194_304;
+/// Length of a frame, in cycles of the system clock (dots).
+pub const FRAME_CYCLES: u64 = 144 * LINE + VBLANK;
 
 #[derive(Copy, Clone, Eq, PartialEq, Hash)]
 pub enum Mode {
@@ -46,8 +56,36 @@ impl Default for GameBoy<(), (), ()> {
 }
 
 impl<C: Cartridge, V: Video, D: Audio> GameBoy<C, V, D> {
+    /// Emulate until the end of the current frame.
     pub fn emulate_frame(&mut self) {
-        self.carry = self.mmu.emulate_frame(&mut self.cpu, self.carry);
+        self.emulate_frame_until(|_| false);
+    }
+
+    /// Emulate until the end of the current frame, or until `stop` holds. `stop` is checked
+    /// before every instruction. Returns true if the emulation was stopped, in which case the
+    /// next call resumes the same frame.
+    pub fn emulate_frame_until<F: FnMut(&Self) -> bool>(&mut self, mut stop: F) -> bool {
+        // the CPU advances the rest of the system on every memory access (see `Mmu::tick`).
+        let start = self.mmu.dots() - self.carry;
+        loop {
+            let dots = self.mmu.dots() - start;
+            if dots >= FRAME_CYCLES {
+                // the overshoot is carried over to the next frame.
+                self.carry = dots % FRAME_CYCLES;
+                return false;
+            }
+            if stop(self) {
+                self.carry = dots;
+                return true;
+            }
+            self.cpu.step(&mut self.mmu);
+        }
+    }
+
+    /// Execute a single instruction (or dispatch a pending interrupt) and return the elapsed
+    /// CPU cycles.
+    pub fn step(&mut self) -> u64 {
+        self.cpu.step(&mut self.mmu)
     }
 
     /// Return the Memory Manager Unit (MMU).
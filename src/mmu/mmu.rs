@@ -1,21 +1,46 @@
 use crate::{
     apu::{device::Audio, apu::Apu},
     cartridge::cartridge::Cartridge,
-    cpu::cpu::Cpu,
+    cpu::cpu::bus,
     device::device::Device,
     interrupt::interrupt::Interrupts,
     joypad::joypad::Joypad,
-    ppu::ppu::{Ppu, Video, HBLANK, PIXELS, SEARCH, VBLANK},
+    ppu::ppu::{Ppu, Video},
+    serial::serial::Serial,
     timer::timer::Timer,
     wram::wram::WRam,
     Mode,
 };
 
+pub mod cdl;
+use cdl::Cdl;
+mod dma;
+use dma::{OamDma, VRamDma};
 
-const HDMA5_DATA: u8 = 0xff;
-const HDMA_DATA: u8 = 0xff; // HDMA1..4
 const HRAM_SIZE: usize = 0x7f;
 
+// Bits of the 0xff00-0xff7f registers that always read as 1. Unmapped and write-only
+// registers read 0xff. SC bit 1 only exists in CGB mode, so the serial port sets it in GB mode.
+#[rustfmt::skip]
+const IO_MASK: [u8; 0x80] = [
+    // P1, SB, SC, DIV, TIMA, TMA, TAC, IF
+    0xc0, 0x00, 0x7c, 0xff, 0x00, 0x00, 0x00, 0xf8, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xe0,
+    // NR10-NR14, NR21-NR24, NR30-NR34
+    0x80, 0x3f, 0x00, 0xff, 0xbf, 0xff, 0x3f, 0x00, 0xff, 0xbf, 0x7f, 0xff, 0x9f, 0xff, 0xbf, 0xff,
+    // NR41-NR44, NR50-NR52
+    0xff, 0x00, 0x00, 0xbf, 0x00, 0x00, 0x70, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    // Wave RAM
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // LCDC, STAT, SCY, SCX, LY, LYC, DMA, BGP, OBP0, OBP1, WY, WX, KEY1, VBK
+    0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x7e, 0xff, 0xfe,
+    // Boot ROM disable, HDMA1-HDMA5
+    0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    // BGPI, BGPD, OBPI, OBPD, OPRI
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x40, 0x00, 0x40, 0x00, 0xfe, 0xff, 0xff, 0xff,
+    // SVBK
+    0xf8, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+];
+
 /// HRam memory
 pub type HRam = Box<[u8; HRAM_SIZE]>;
 
@@ -26,23 +51,6 @@ enum Speed {
     X2 = 0x80,
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
-struct VRamDma {
-    hdma1: u8,
-    hdma2: u8,
-    hdma3: u8,
-    hdma4: u8,
-}
-
-impl Default for VRamDma {
-    fn default() -> Self {
-        Self { hdma1: 0,
-               hdma2: 0,
-               hdma3: 0,
-               hdma4: 0 }
-    }
-}
-
 
 pub struct Mmu<C: Cartridge, V: Video, D: Audio> {
     #[cfg_attr(not(feature = "dmg-data"), allow(dead_code))]
@@ -52,12 +60,21 @@ pub struct Mmu<C: Cartridge, V: Video, D: Audio> {
     ppu: Ppu<V>,
     apu: Apu<D>,
     timer: Timer,
+    serial: Serial,
     wram: WRam,
     joy: Joypad,
     hram: HRam,
+    oam_dma: OamDma,
     vram_dma: VRamDma,
     int: Interrupts,
     speed: Speed,
+    // KEY1 bit 0. Set by the game to request a speed switch on the next STOP.
+    speed_switch: bool,
+    // M-cycles the CPU is halted for, while VRAM DMA blocks are copied.
+    stall: u64,
+    // Dots elapsed since power on.
+    dots: u64,
+    cdl: Option<Cdl>,
 }
 
 impl<C: Cartridge, V: Video, D: Audio> Mmu<C, V, D> {
@@ -67,13 +84,19 @@ impl<C: Cartridge, V: Video, D: Audio> Mmu<C, V, D> {
                boot: false,
                ppu: Ppu::new(mode, video_out),
                timer: Timer::default(),
+               serial: Serial::new(mode),
                wram: WRam::default(),
                joy: Joypad::default(),
                apu: Apu::default(),
                hram: Box::new([0; HRAM_SIZE]),
+               oam_dma: OamDma::default(),
                vram_dma: VRamDma::default(),
                int: Interrupts::default(),
-               speed: Speed::X1 }
+               speed: Speed::X1,
+               speed_switch: false,
+               stall: 0,
+               dots: 0,
+               cdl: None }
     }
 
     pub fn cartridge(&self) -> &C {
@@ -84,6 +107,19 @@ impl<C: Cartridge, V: Video, D: Audio> Mmu<C, V, D> {
         &mut self.cartridge
     }
 
+    /// Installs (or removes, with `None`) the code/data log. Returns the previous one.
+    pub fn set_cdl(&mut self, cdl: Option<Cdl>) -> Option<Cdl> {
+        std::mem::replace(&mut self.cdl, cdl)
+    }
+
+    pub fn cdl(&self) -> Option<&Cdl> {
+        self.cdl.as_ref()
+    }
+
+    pub fn cdl_mut(&mut self) -> Option<&mut Cdl> {
+        self.cdl.as_mut()
+    }
+
     pub fn joypad(&self) -> &Joypad {
         &self.joy
     }
@@ -92,6 +128,14 @@ impl<C: Cartridge, V: Video, D: Audio> Mmu<C, V, D> {
         &mut self.joy
     }
 
+    pub fn serial(&self) -> &Serial {
+        &self.serial
+    }
+
+    pub fn serial_mut(&mut self) -> &mut Serial {
+        &mut self.serial
+    }
+
     pub fn ppu(&self) -> &Ppu<V> {
         &self.ppu
     }
@@ -132,36 +176,28 @@ impl<C: Cartridge, V: Video, D: Audio> Mmu<C, V, D> {
         &mut self.hram
     }
 
-    pub(crate) fn emulate_frame(&mut self, cpu: &mut Cpu, carry: u64) -> u64 {
-        const FRAME_CYCLES: u64 = 144 * (SEARCH + PIXELS + HBLANK) + VBLANK;
-
-        let mut cycles = carry;
-        let  cpu_rem = 0;
-        while cycles < FRAME_CYCLES {
-            let mut cpu_cycles = cpu.step(self);
-
-            if self.speed == Speed::X2 {
-                cpu_cycles /= 2;
-            }
-
-            self.step(cpu_rem + cpu_cycles);
-            cycles += cpu_cycles;
-        }
-
-        // return carry. This value should be passed as carry argument on the next call
-        // to this method.
-        cycles % FRAME_CYCLES
+    /// Returns the dots (cycles of the 4 MHz clock, which CGB double speed doesn't affect)
+    /// elapsed since power on.
+    pub fn dots(&self) -> u64 {
+        self.dots
     }
 
-    // Advance the mapped components by the given amount of cycles of the internal
-    // 4MHz clock.
-    fn step(&mut self, cycles: u64) {
+    // Advance the mapped components. `cycles` are CPU cycles and `dots` are cycles of the
+    // internal 4MHz clock (they only differ in CGB double speed mode).
+    fn step(&mut self, cycles: u64, dots: u64) {
         if let Some(int) = self.joy.take_int() {
             self.int.set(int);
         }
-        self.ppu.step(cycles);
+        for _ in 0..cycles / 4 {
+            self.step_oam_dma();
+        }
+        self.ppu.step(dots);
+        if self.ppu.take_hblank() && self.vram_dma.hblank() {
+            self.vram_dma_block();
+        }
         self.timer.step(cycles);
-        self.apu.lock().step(cycles);
+        self.serial.step(cycles);
+        self.apu.lock().step(dots);
 
         // request generated interrupts
         if let Some(flag) = self.ppu.take_vblank_int() {
@@ -173,37 +209,130 @@ impl<C: Cartridge, V: Video, D: Audio> Mmu<C, V, D> {
         if let Some(flag) = self.timer.take_timer_int() {
             self.int.set(flag);
         }
+        if let Some(flag) = self.serial.take_serial_int() {
+            self.int.set(flag);
+        }
+    }
+
+    // CGB-only I/O registers (KEY1, VBK, HDMA, color palettes, OPRI and SVBK). In GB mode they
+    // read 0xff and ignore writes.
+    fn cgb_only(&self, addr: u16) -> bool {
+        self.mode == Mode::GB && matches!(addr, 0xff4d | 0xff4f | 0xff51..=0xff55 | 0xff68..=0xff6c | 0xff70)
     }
 
-   
-    fn oam_dma(&mut self, d: u8) {
-        let src = u16::from(d) << 8;
-        let dst = 0xfe00;
+    // Copies the OAM DMA byte of the current M-cycle.
+    fn step_oam_dma(&mut self) {
+        if let Some((src, dst)) = self.oam_dma.tick() {
+            let data = match src {
+                0x8000..=0x9fff => self.ppu.vram().read(src),
+                _ => Device::read(self, src),
+            };
+            self.oam_dma.copied(data);
+            self.ppu.oam_mut().write(dst, data);
+        }
+    }
 
-        for addr in 0..=0x9f {
-            let src = src | (addr as u16);
-            let dst = dst | (addr as u16);
-            self.write(dst, self.read(src));
+    // While an OAM DMA transfer is running, the CPU can't access OAM, nor the bus used by the
+    // transfer (DMG layout: the video bus for VRAM, the external bus for everything else below
+    // OAM). Returns the value read by the CPU on such an access: 0xff for OAM, or the byte
+    // being copied.
+    fn oam_dma_conflict(&self, addr: u16) -> Option<u8> {
+        let video = |addr| (0x8000..=0x9fff).contains(&addr);
+        match addr {
+            _ if !self.oam_dma.active() => None,
+            0xfe00..=0xfeff => Some(0xff),
+            0x0000..=0xfdff if video(addr) == video(self.oam_dma.src()) => Some(self.oam_dma.data()),
+            _ => None,
         }
     }
 
  
     fn vram_dma(&mut self, hdma5: u8) {
-        let hdma1 = self.vram_dma.hdma1;
-        let hdma2 = self.vram_dma.hdma2;
-        let hdma3 = self.vram_dma.hdma3;
-        let hdma4 = self.vram_dma.hdma4;
-
-        let src = (u16::from(hdma1) << 8) | u16::from(hdma2 & 0xf0);
-        let dst = 0x8000 | (u16::from(hdma3 & 0x1f) << 8) | u16::from(hdma4 & 0xf0);
-        let len = (u16::from(hdma5 & 0x7f) + 1) * 16;
-
-        let src = src..src + len;
-        let dst = dst..dst + len;
-        for (src, dst) in src.zip(dst) {
-            let src = self.read(src);
-            self.write(dst, src);
+        if self.vram_dma.start(hdma5) {
+            for _ in 0..self.vram_dma.blocks() {
+                self.vram_dma_block();
+            }
+        } else if self.vram_dma.hblank() && self.ppu.lcdc_stat().lcdc & 0x80 == 0 {
+            // With the LCD off, the first block is copied right away.
+            self.vram_dma_block();
+        }
+    }
+
+    // Copies the next VRAM DMA block, and halts the CPU for 8 M-cycles in normal speed (16 in
+    // double speed).
+    fn vram_dma_block(&mut self) {
+        let (src, dst) = self.vram_dma.next_block();
+        for i in 0..0x10 {
+            let data = Device::read(self, src.wrapping_add(i));
+            self.ppu.vram_mut().write(dst + i, data);
         }
+        self.stall += match self.speed {
+            Speed::X1 => 8,
+            Speed::X2 => 16,
+        };
+    }
+}
+
+impl<C: Cartridge, V: Video, D: Audio> bus::Bus for Mmu<C, V, D> {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.oam_dma_conflict(addr).unwrap_or_else(|| Device::read(self, addr))
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        if self.oam_dma_conflict(addr).is_none() {
+            Device::write(self, addr, data)
+        }
+    }
+
+    /// Advances the mapped components by one M-cycle of the CPU, plus the M-cycles the CPU
+    /// is halted for by VRAM DMA transfers.
+    fn tick(&mut self) {
+        // In double speed mode the CPU and the timer run twice as fast, while the PPU and
+        // the APU keep running at the normal rate.
+        let dots = match self.speed {
+            Speed::X1 => 4,
+            Speed::X2 => 2,
+        };
+        self.step(4, dots);
+        self.dots += dots;
+        while self.stall > 0 {
+            self.stall -= 1;
+            self.step(4, dots);
+            self.dots += dots;
+        }
+    }
+
+    fn pending(&self) -> u8 {
+        self.int.pending()
+    }
+
+    fn acknowledge(&mut self, int: u8) {
+        self.int.acknowledge(int)
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.cartridge.rom_bank()
+    }
+
+    fn log(&mut self, addr: u16, flag: u8) {
+        if let Some(cdl) = self.cdl.as_mut() {
+            cdl.mark(addr, self.cartridge.rom_bank(), flag);
+        }
+    }
+
+    /// Performs the CGB speed switch if it has been armed through the KEY1 register.
+    fn speed_switch(&mut self) -> bool {
+        if self.mode != Mode::CGB || !self.speed_switch {
+            return false;
+        }
+        self.speed = match self.speed {
+            Speed::X1 => Speed::X2,
+            Speed::X2 => Speed::X1,
+        };
+        self.speed_switch = false;
+        // STOP resets the divider.
+        self.timer.write(0xff04, 0);
+        true
     }
 }
 
@@ -233,29 +362,24 @@ impl<C: Cartridge, V: Video, D: Audio> Device for Mmu<C, V, D> {
             0xc000..=0xdfff => self.wram.read(addr),
             0xe000..=0xfdff => self.wram.read(addr),
             0xfe00..=0xfe9f => self.ppu.read(addr),
-            0xfea0..=0xfeff => 0,
-            0xff00..=0xff7f => match addr {
+            0xfea0..=0xfeff => 0xff,
+            0xff00..=0xff7f => IO_MASK[addr as usize - 0xff00] | match addr {
+                _ if self.cgb_only(addr) => 0xff,
                 0xff00 => self.joy.read(addr),
-                0xff01 | 0xff02 => 0,
+                0xff01 | 0xff02 => self.serial.read(addr),
                 0xff04..=0xff07 => self.timer.read(addr),
                 0xff0f => self.int.read(addr),
                 0xff10..=0xff14
                 | 0xff16..=0xff19
                 | 0xff1a..=0xff1e
                 | 0xff30..=0xff3f
-                | 0xff20..=0xff26
-                | 0xff27..=0xff2f => self.apu.read(addr),
-                0xff40..=0xff45 | 0xff47..=0xff4b | 0xff4f | 0xff68..=0xff6b => self.ppu.read(addr),
-                0xff46 => 0, // OAM DMA
-                0xff50 => 0,
-                0xff51..=0xff54 => HDMA_DATA,
-                0xff55 => HDMA5_DATA,
-                0xff4d => self.speed as u8,
+                | 0xff20..=0xff26 => self.apu.read(addr),
+                0xff40..=0xff45 | 0xff47..=0xff4b | 0xff4f | 0xff68..=0xff6c => self.ppu.read(addr),
+                0xff46 => self.oam_dma.reg(),
+                0xff55 => self.vram_dma.hdma5(),
+                0xff4d => self.speed as u8 | u8::from(self.speed_switch),
                 0xff70 => self.wram.read(addr),
-                _ => {
-                    //println!("ERROR {:04x}", addr);
-                    0
-                }
+                _ => 0xff,
             },
             0xff80..=0xfffe => self.hram[addr as usize - 0xff80],
             0xffff => self.int.read(addr),
@@ -272,8 +396,9 @@ impl<C: Cartridge, V: Video, D: Audio> Device for Mmu<C, V, D> {
             0xfe00..=0xfe9f => self.ppu.write(addr, data),
             0xfea0..=0xfeff => { /* Not Usable */ }
             0xff00..=0xff7f => match addr {
+                _ if self.cgb_only(addr) => {}
                 0xff00 => self.joy.write(addr, data),
-                0xff01 | 0xff02 => { /* serial data transfer (link cable) */ }
+                0xff01 | 0xff02 => self.serial.write(addr, data),
                 0xff04..=0xff07 => self.timer.write(addr, data),
                 0xff0f => self.int.write(addr, data),
                 0xff10..=0xff14
@@ -282,27 +407,20 @@ impl<C: Cartridge, V: Video, D: Audio> Device for Mmu<C, V, D> {
                 | 0xff30..=0xff3f
                 | 0xff20..=0xff26
                 | 0xff27..=0xff2f => self.apu.write(addr, data),
-                0xff40..=0xff45 | 0xff47..=0xff4b | 0xff4f | 0xff68..=0xff6b => {
+                0xff40..=0xff45 | 0xff47..=0xff4b | 0xff4f | 0xff68..=0xff6c => {
                     self.ppu.write(addr, data)
                 }
-                0xff46 => self.oam_dma(data),
+                0xff46 => self.oam_dma.start(data),
                 0xff50 => {
                     if !self.boot {
                         self.boot = data & 0x1 != 0;
                     }
                 }
-                0xff51 => self.vram_dma.hdma1 = data,
-                0xff52 => self.vram_dma.hdma2 = data,
-                0xff53 => self.vram_dma.hdma3 = data,
-                0xff54 => self.vram_dma.hdma4 = data,
+                0xff51..=0xff54 => self.vram_dma.write_addr(addr, data),
                 0xff55 => self.vram_dma(data),
 
                 // KEY1
-                0xff4d => {
-                    if data & 0x1 != 0 {
-                        self.speed = Speed::X2;
-                    }
-                }
+                0xff4d => self.speed_switch = data & 0x1 != 0,
                 0xff70 => self.wram.write(addr, data),
                 _ => {}
             },
@@ -0,0 +1,63 @@
+pub use crate::cpu::cpu::bus::cdl::{CODE, DATA, WRITE};
+use std::io::{self, Write};
+
+/// Code/data log.
+///
+/// Marks every byte accessed by the CPU with [`CODE`], [`DATA`] and [`WRITE`] flags. ROM bytes
+/// are logged by offset into the ROM image (so banked code is told apart). Everything else
+/// (VRAM, cartridge RAM, WRAM, I/O and HRAM) is logged by CPU address, from 0x8000 up.
+#[derive(Debug, Clone)]
+pub struct Cdl {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+}
+
+impl Cdl {
+    /// Creates an empty log for a ROM of `rom_size` bytes.
+    pub fn new(rom_size: usize) -> Self {
+        Self { rom: vec![0; rom_size],
+               ram: vec![0; 0x8000] }
+    }
+
+    /// Returns the flags of every byte of the ROM image.
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    /// Returns the flags of the 0x8000-0xffff address range.
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    /// Returns the number of ROM bytes that were executed, read as data, and never accessed.
+    pub fn rom_stats(&self) -> (usize, usize, usize) {
+        let count = |flag: u8| self.rom.iter().filter(|&&f| f & flag != 0).count();
+        let untouched = self.rom.iter().filter(|&&f| f == 0).count();
+        (count(CODE), count(DATA), untouched)
+    }
+
+    pub fn clear(&mut self) {
+        self.rom.iter_mut().for_each(|f| *f = 0);
+        self.ram.iter_mut().for_each(|f| *f = 0);
+    }
+
+    /// Writes the ROM log in the usual `.cdl` layout: one byte of flags per ROM byte, with
+    /// bit 0 set for code and bit 1 for data.
+    pub fn write_cdl<W: Write>(&self, mut out: W) -> io::Result<()> {
+        out.write_all(&self.rom)
+    }
+
+    // Marks the byte at `addr`, with `bank` mapped at 0x4000-0x7fff.
+    pub(crate) fn mark(&mut self, addr: u16, bank: usize, flag: u8) {
+        // ROM writes are MBC register accesses.
+        let flag = if addr < 0x8000 { flag & !WRITE } else { flag };
+        let flags = match addr {
+            0x0000..=0x3fff => self.rom.get_mut(addr as usize),
+            0x4000..=0x7fff => self.rom.get_mut(bank * 0x4000 + addr as usize - 0x4000),
+            _ => self.ram.get_mut(addr as usize - 0x8000),
+        };
+        if let Some(flags) = flags {
+            *flags |= flag;
+        }
+    }
+}
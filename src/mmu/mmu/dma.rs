@@ -0,0 +1,153 @@
+/// Bytes copied by an OAM DMA transfer, one per M-cycle.
+const OAM_DMA_LEN: u8 = 0xa0;
+
+/// OAM DMA engine.
+///
+/// Writing 0xff46 starts a transfer after a startup M-cycle. Writing it again while a transfer
+/// is running restarts it from the first byte, and the previous transfer keeps running during
+/// the startup M-cycle of the new one.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct OamDma {
+    // Last value written to 0xff46.
+    reg: u8,
+    // Source of the transfer being started.
+    start: Option<u16>,
+    src: u16,
+    // Next byte to be copied, if a transfer is running.
+    index: Option<u8>,
+    // A byte was copied during the current M-cycle.
+    active: bool,
+    // Last byte copied.
+    data: u8,
+}
+
+impl OamDma {
+    pub fn reg(&self) -> u8 {
+        self.reg
+    }
+
+    /// Starts (or restarts) a transfer from `reg << 8`.
+    pub fn start(&mut self, reg: u8) {
+        // 0xe000-0xffff sources are mapped to work RAM, like echo RAM.
+        let src = u16::from(reg) << 8;
+        self.reg = reg;
+        self.start = Some(if src >= 0xe000 { src - 0x2000 } else { src });
+    }
+
+    /// A transfer is using the bus during the current M-cycle.
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    pub fn src(&self) -> u16 {
+        self.src
+    }
+
+    /// Byte being copied during the current M-cycle.
+    pub fn data(&self) -> u8 {
+        self.data
+    }
+
+    /// Advances by one M-cycle. Returns the source and destination addresses of the byte to be
+    /// copied during this M-cycle, if any. The copied byte must be passed to `copied`.
+    pub fn tick(&mut self) -> Option<(u16, u16)> {
+        let copy = self.index.map(|i| {
+            self.index = Some(i + 1).filter(|&i| i < OAM_DMA_LEN);
+            (self.src | u16::from(i), 0xfe00 | u16::from(i))
+        });
+        self.active = copy.is_some();
+        if let Some(src) = self.start.take() {
+            self.src = src;
+            self.index = Some(0);
+        }
+        copy
+    }
+
+    pub fn copied(&mut self, data: u8) {
+        self.data = data;
+    }
+}
+
+/// CGB VRAM DMA (HDMA) engine.
+///
+/// Transfers are made of 16-byte blocks. A general purpose transfer copies every block at
+/// once, while an HBlank transfer copies one block at the beginning of each HBlank. The
+/// source and destination registers advance with every block.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct VRamDma {
+    src: u16,
+    // Offset within VRAM.
+    dst: u16,
+    // Blocks left minus one (HDMA5 bits 0-6).
+    len: u8,
+    // An HBlank transfer is running.
+    hblank: bool,
+}
+
+impl Default for VRamDma {
+    fn default() -> Self {
+        Self { src: 0,
+               dst: 0,
+               len: 0x7f,
+               hblank: false }
+    }
+}
+
+impl VRamDma {
+    /// Writes one of the HDMA1-HDMA4 address registers.
+    pub fn write_addr(&mut self, addr: u16, data: u8) {
+        let data = u16::from(data);
+        match addr {
+            0xff51 => self.src = (self.src & 0x00f0) | (data << 8),
+            0xff52 => self.src = (self.src & 0xff00) | (data & 0xf0),
+            0xff53 => self.dst = (self.dst & 0x00f0) | ((data & 0x1f) << 8),
+            0xff54 => self.dst = (self.dst & 0x1f00) | (data & 0xf0),
+            _ => panic!(),
+        }
+    }
+
+    /// HDMA5: bit 7 is clear while an HBlank transfer is running, and bits 0-6 hold the blocks
+    /// left minus one (0xff once a transfer is complete).
+    pub fn hdma5(&self) -> u8 {
+        if self.hblank {
+            self.len
+        } else {
+            0x80 | self.len
+        }
+    }
+
+    /// Writes HDMA5. Returns true if a general purpose transfer must be performed.
+    ///
+    /// Writing bit 7 clear while an HBlank transfer is running cancels it.
+    pub fn start(&mut self, hdma5: u8) -> bool {
+        if self.hblank && hdma5 & 0x80 == 0 {
+            self.hblank = false;
+            return false;
+        }
+        self.len = hdma5 & 0x7f;
+        self.hblank = hdma5 & 0x80 != 0;
+        !self.hblank
+    }
+
+    /// An HBlank transfer is running.
+    pub fn hblank(&self) -> bool {
+        self.hblank
+    }
+
+    /// Blocks left, for a general purpose transfer.
+    pub fn blocks(&self) -> usize {
+        usize::from(self.len) + 1
+    }
+
+    /// Returns the source and destination (VRAM) address of the next block, and advances.
+    pub fn next_block(&mut self) -> (u16, u16) {
+        let block = (self.src, 0x8000 | self.dst);
+        self.src = self.src.wrapping_add(0x10);
+        self.dst = (self.dst + 0x10) & 0x1ff0;
+        self.len = self.len.wrapping_sub(1) & 0x7f;
+        if self.len == 0x7f {
+            self.hblank = false;
+        }
+        block
+    }
+}
@@ -2,6 +2,7 @@ use crate::{
     device::device::Device,
     interrupt::interrupt::Flag,
     ppu::ppu::{
+        fifo::{Fetcher, Pixel, Renderer, Sprite, MAX_SPRITES},
         oam::{Entry, Oam},
         palette::Color,
         reg::{
@@ -15,6 +16,7 @@ use crate::{
 use reg::{ColorPal, Line, Pal, Scroll, Window};
 use std::mem;
 
+mod fifo;
 pub mod oam;
 pub mod palette;
 pub mod reg;
@@ -23,9 +25,10 @@ pub const LCD_WIDTH: usize = 160;
 pub const LCD_HEIGHT: usize = 144;
 
 pub(crate) const SEARCH: u64 = 80; //  80 dots (19 us)
-pub(crate) const PIXELS: u64 = 230; // 168 to 291 cycles (40 to 60 us) depending on sprite count
-pub(crate) const HBLANK: u64 = 147; // 85 to 208 dots (20 to 49 us) depending on previous mode 3 duration
+pub(crate) const PIXELS: u64 = 172; // 172 to 289 dots (41 to 69 us) depending on SCX, window and sprites
+pub(crate) const HBLANK: u64 = 204; // 87 to 204 dots (21 to 49 us) depending on previous mode 3 duration
 pub(crate) const VBLANK: u64 = 4560; // 4560 dots (1087 us, 10 scanlines)
+pub(crate) const LINE: u64 = SEARCH + PIXELS + HBLANK; // 456 dots
 
 /// Display scanline renderer.
 pub trait Video {
@@ -36,15 +39,32 @@ impl Video for () {
     fn draw_video(&mut self, _: &[[Color; LCD_WIDTH]; LCD_HEIGHT]) {}
 }
 
+/// Keeps the last frame drawn, for headless frontends and tests.
+#[derive(Default)]
+pub struct Framebuffer(Vec<Color>);
+
+impl Framebuffer {
+    /// Returns the pixels of the last frame row by row, or nothing if no frame was drawn yet.
+    pub fn pixels(&self) -> &[Color] {
+        &self.0
+    }
+}
+
+impl Video for Framebuffer {
+    fn draw_video(&mut self, pixels: &[[Color; LCD_WIDTH]; LCD_HEIGHT]) {
+        self.0.clear();
+        self.0.extend(pixels.iter().flatten());
+    }
+}
+
 pub struct Ppu<V: Video> {
     video: V,
     mode: Mode,
+    // Dots elapsed since the beginning of the current line.
     // Same as cycles, but documentation often refers to it as "dots" instead of cycles.
     dots: u64,
     buffer: Box<[[Color; LCD_WIDTH]; LCD_HEIGHT]>,
-    // Color index within color palette.
-    // Used to tell when a sprite is behind a BG tile.
-    color_index: Box<[[u8; LCD_WIDTH]; LCD_HEIGHT]>,
+    renderer: Renderer,
     stat_mode: StatMode,
     vram: VRam,
     oam: Oam,
@@ -52,10 +72,24 @@ pub struct Ppu<V: Video> {
     scroll: Scroll,
     line: Line,
     win: Window,
+    // Window internal line counter. It only advances on lines where the window was drawn.
+    win_line: u8,
+    // LY matched WY at the beginning of a line of the current frame.
+    win_ly: bool,
+    // The window was started at WX=166, so it covers the whole next line.
+    win_next: bool,
     pal: Pal,
     color_pal: ColorPal,
+    // CGB object priority mode (OPRI). Bit 0 clear for OAM index priority, set for X priority.
+    opri: u8,
+    // STAT interrupt line.
+    stat_irq: bool,
+    // Ignore the VRAM, OAM and palette access restrictions.
+    permissive: bool,
     vblank_int: Option<Flag>,
     lcdc_int: Option<Flag>,
+    // HBlank was entered during the last update (HBlank DMA trigger).
+    hblank: bool,
 }
 
 impl<V: Video> Ppu<V> {
@@ -64,7 +98,7 @@ impl<V: Video> Ppu<V> {
                video: output,
                mode,
                buffer: Box::new([[[0xff, 0xff, 0xff]; LCD_WIDTH]; LCD_HEIGHT]),
-               color_index: Box::new([[0; LCD_WIDTH]; LCD_HEIGHT]),
+               renderer: Renderer::default(),
                vram: VRam::default(),
                oam: Oam::default(),
                stat_mode: StatMode::HBlank,
@@ -72,10 +106,17 @@ impl<V: Video> Ppu<V> {
                scroll: Scroll::default(),
                line: Line::default(),
                win: Window::default(),
+               win_line: 0,
+               win_ly: false,
+               win_next: false,
                pal: Pal::default(),
                color_pal: ColorPal::default(),
+               opri: 0,
+               stat_irq: false,
+               permissive: false,
                vblank_int: None,
-               lcdc_int: None }
+               lcdc_int: None,
+               hblank: false }
     }
 
     pub fn lcdc_stat(&self) -> &LcdcStat {
@@ -86,10 +127,34 @@ impl<V: Video> Ppu<V> {
         &self.win
     }
 
+    pub fn vram(&self) -> &VRam {
+        &self.vram
+    }
+
     pub fn vram_mut(&mut self) -> &mut VRam {
         &mut self.vram
     }
 
+    pub fn oam(&self) -> &Oam {
+        &self.oam
+    }
+
+    pub fn oam_mut(&mut self) -> &mut Oam {
+        &mut self.oam
+    }
+
+    /// Lifts the access restrictions, for debugging software that doesn't respect them.
+    ///
+    /// By default, VRAM and the CGB palette data (BGPD and OBPD) can't be accessed during
+    /// mode 3, nor OAM during modes 2 and 3: reads return 0xff and writes are ignored.
+    pub fn set_permissive(&mut self, permissive: bool) {
+        self.permissive = permissive;
+    }
+
+    pub fn permissive(&self) -> bool {
+        self.permissive
+    }
+
     /// Return the color palette register
     pub fn color_pal(&self) -> &ColorPal {
         &self.color_pal
@@ -142,102 +207,131 @@ impl<V: Video> Ppu<V> {
         }
     }
 
-    pub fn step(&mut self, cycles: u64) {
+    pub fn step(&mut self, dots: u64) {
         if self.lcdc_stat.lcdc & 0x80 == 0 {
             return;
         }
+        for _ in 0..dots {
+            self.dot();
+        }
+    }
 
-        self.dots += cycles;
-
+    // Advances the PPU by one dot.
+    fn dot(&mut self) {
         let mut line = self.line.ly;
 
-        let next_state = match self.stat_mode {
-            StatMode::Search if self.dots >= SEARCH => StatMode::Pixels,
-            StatMode::Pixels if self.dots >= PIXELS => StatMode::HBlank,
-            StatMode::HBlank if self.dots >= HBLANK => StatMode::Search,
-            StatMode::VBlank if self.dots >= VBLANK => StatMode::Search,
-            _ => self.stat_mode,
-        };
+        match self.stat_mode {
+            StatMode::Search => self.scan_dot(),
+            StatMode::Pixels => self.render_dot(),
+            _ => {}
+        }
+        self.dots += 1;
 
-        // handle state transitions.
-        match (self.stat_mode, next_state) {
-            (StatMode::Search, StatMode::Search) => {}
-            (StatMode::Search, StatMode::Pixels) => {
-                self.dots %= SEARCH;
+        match self.stat_mode {
+            StatMode::Search if self.dots == SEARCH => {
                 self.stat_mode = StatMode::Pixels;
+                self.start_line();
             }
-
-            (StatMode::Pixels, StatMode::Pixels) => { /* TODO dot by dot */ }
-            (StatMode::Pixels, StatMode::HBlank) => {
-                self.draw_line(line, 0, LCD_WIDTH);
-                self.dots %= PIXELS;
-                self.stat_mode = StatMode::HBlank;
-                if self.lcdc_stat.stat & STAT_HBLANK_FLAG != 0 {
-                    self.request_lcdc();
+            StatMode::Pixels if self.renderer.lx as usize == LCD_WIDTH => {
+                if self.renderer.window {
+                    self.win_line = self.win_line.wrapping_add(1);
                 }
+                self.win_next = self.renderer.window && self.win.wx == 166;
+                self.stat_mode = StatMode::HBlank;
+                self.hblank = true;
             }
-
-            (StatMode::HBlank, StatMode::HBlank) => {}
-            (StatMode::HBlank, StatMode::Search) if line == 143 => {
-                self.dots %= HBLANK;
+            StatMode::HBlank if self.dots == LINE && line == 143 => {
+                self.dots = 0;
                 self.stat_mode = StatMode::VBlank;
+                self.reset_window();
                 self.request_vblank();
-                if self.lcdc_stat.stat & STAT_VBLANK_FLAG != 0 {
-                    self.request_lcdc();
-                }
                 self.video.draw_video(&self.buffer);
                 line = 144;
             }
-            (StatMode::HBlank, StatMode::Search) => {
-                self.dots %= HBLANK;
+            StatMode::HBlank if self.dots == LINE => {
+                self.dots = 0;
                 self.stat_mode = StatMode::Search;
-                if self.lcdc_stat.stat & STAT_SEARCH_FLAG != 0 {
-                    self.request_lcdc();
-                }
                 line += 1;
-
-                // search 10 visible sprites in new line
-                //let h = self.lcdc_stat.lcdc_ob_size();
-                //self.oam.search(line, h);
             }
-
-            (StatMode::VBlank, StatMode::Search) => {
-                self.dots %= VBLANK;
+            // LY was reset at the beginning of line 153.
+            StatMode::VBlank if self.dots == LINE && line == 0 => {
+                self.dots = 0;
                 self.stat_mode = StatMode::Search;
-                if self.lcdc_stat.stat & STAT_SEARCH_FLAG != 0 {
-                    self.request_lcdc();
-                }
-                line = 0;
             }
-            (StatMode::VBlank, StatMode::VBlank) => {
-                const DOTS_PER_LINE: u64 = 456;
-
-                if line != 0 {
-                    line = 144 + (self.dots / DOTS_PER_LINE) as u8;
-                }
-
-                // some documents claim that the LY counter is reset in the middle of the last
-                // VBLANK line.
-                if line == 153 && self.dots % DOTS_PER_LINE > DOTS_PER_LINE / 2 {
-                    line = 0;
-                }
+            StatMode::VBlank if self.dots == LINE => {
+                self.dots = 0;
+                line += 1;
             }
+            // LY reads 153 for the first M-cycle of line 153 only.
+            StatMode::VBlank if line == 153 && self.dots == 4 => line = 0,
+            _ => {}
+        }
 
-            _ => panic!(),
+        self.line.ly = line;
+        self.update_stat();
+    }
+
+    // Value LYC is compared against, if any.
+    //
+    // The comparison is off during the first M-cycle of every line but line 0. On line 153 LY
+    // reads 0 after the first M-cycle but LYC is still compared against 153 for another M-cycle,
+    // then the comparison is off again for one M-cycle.
+    fn ly_compare(&self) -> Option<u8> {
+        let ly = self.line.ly;
+        match (self.stat_mode, self.dots) {
+            (StatMode::VBlank, 4..=7) if ly == 0 => Some(153),
+            (StatMode::VBlank, 8..=11) if ly == 0 => None,
+            (_, 0..=3) if ly != 0 => None,
+            _ => Some(ly),
         }
+    }
 
-        // LY=LYC
-        if line == self.line.lyc {
-            if line != self.line.ly && self.lcdc_stat.stat & STAT_LYC_LY_FLAG != 0 {
-                self.request_lcdc();
+    // State of the STAT interrupt line: the OR of all the enabled STAT conditions. The OAM
+    // condition is also asserted on the first dot of line 144.
+    fn stat_line(&self) -> bool {
+        let stat = self.lcdc_stat.stat;
+        let mode = match self.stat_mode {
+            StatMode::HBlank => stat & STAT_HBLANK_FLAG != 0,
+            StatMode::VBlank => {
+                stat & STAT_VBLANK_FLAG != 0
+                    || stat & STAT_SEARCH_FLAG != 0 && self.line.ly == 144 && self.dots == 0
             }
+            StatMode::Search => stat & STAT_SEARCH_FLAG != 0,
+            StatMode::Pixels => false,
+        };
+        self.lcdc_stat.lcdc & 0x80 != 0 && (mode || stat & STAT_LYC_LY_FLAG != 0 && stat & 0x4 != 0)
+    }
+
+    // Updates the mode and LY=LYC bits of STAT, and requests an interrupt on a rising edge of the
+    // STAT interrupt line. Requests are blocked for as long as the line stays high, even if the
+    // condition that raised it is replaced by another one.
+    fn update_stat(&mut self) {
+        if self.ly_compare() == Some(self.line.lyc) {
             self.lcdc_stat.stat |= 0b0000_0100;
         } else {
             self.lcdc_stat.stat &= 0b1111_1011;
         }
-
-        self.line.ly = line;
         self.lcdc_stat.stat_set_mode(self.stat_mode);
+
+        let line = self.stat_line();
+        if line && !self.stat_irq {
+            self.request_lcdc();
+        }
+        self.stat_irq = line;
+    }
+
+    fn vram_blocked(&self) -> bool {
+        !self.permissive && matches!(self.stat_mode, StatMode::Pixels)
+    }
+
+    fn oam_blocked(&self) -> bool {
+        !self.permissive && matches!(self.stat_mode, StatMode::Search | StatMode::Pixels)
+    }
+
+    fn reset_window(&mut self) {
+        self.win_line = 0;
+        self.win_ly = false;
+        self.win_next = false;
     }
 
     fn request_vblank(&mut self) {
@@ -258,39 +352,20 @@ impl<V: Video> Ppu<V> {
         self.lcdc_int.take()
     }
 
+    // Must be called by the MMU after an update
+    pub(crate) fn take_hblank(&mut self) -> bool {
+        std::mem::take(&mut self.hblank)
+    }
+
     fn clear_video(&mut self) {
         let color = match self.mode {
             Mode::GB => self.pal.clear_color(),
             Mode::CGB => self.color_pal.clear_color(),
         };
         mem::replace(self.buffer.as_mut(), [[color; LCD_WIDTH]; LCD_HEIGHT]);
-        mem::replace(self.color_index.as_mut(), [[0; LCD_WIDTH]; LCD_HEIGHT]);
         self.video.draw_video(&self.buffer);
     }
 
-    fn draw_line(&mut self, ly: u8, offset: usize, dots: usize) {
-        let lcdc_0 = self.lcdc_stat.lcdc & 0x1 != 0;
-
-        // Behavior of the LCD registers is different on each game boy model.
-        // In CGB mode (game boy color), the BG is always visible.
-        let display_bg = match self.mode {
-            Mode::GB => lcdc_0,
-            Mode::CGB => true,
-        };
-
-        if display_bg {
-            self.draw_bg(ly, offset, dots);
-        }
-        let display_win = self.lcdc_stat.lcdc & 0x20 != 0;
-        if display_win {
-            self.draw_win(ly, offset, dots);
-        }
-        let display_ob = self.lcdc_stat.lcdc & 0x2 != 0;
-        if display_ob {
-            self.draw_ob(ly, offset, dots);
-        }
-    }
-
     // fetch pixel color from a given coordinate
     // coordinate is relative to the tilemap origin
     #[rustfmt::skip]
@@ -309,14 +384,7 @@ impl<V: Video> Ppu<V> {
             if flags & 0x20 != 0 { col = 7 - col }
             if flags & 0x40 != 0 { row = 7 - row }
         }
-        let offset = match data {
-            TileDataAddr::X8000 => 16 * (tile as usize) + row * 2,
-            TileDataAddr::X8800 => {
-                let tile: i8 = unsafe { mem::transmute(tile) };
-                let tile = (tile as isize + 128) as usize;
-                0x800 + 16 * tile + row * 2
-            }
-        };
+        let offset = tile_data_offset(data, tile) + row * 2;
         let bank = match self.mode {
             Mode::GB => 0,
             Mode::CGB => (flags >> 3) & 0x1,
@@ -324,201 +392,301 @@ impl<V: Video> Ppu<V> {
         // decode color index from tile data
         let lo = self.vram.bank(bank)[offset] >> (col as u8) & 0x1;
         let hi = self.vram.bank(bank)[offset + 1] >> (col as u8) & 0x1;
-        let mut color_index = lo | (hi << 1);
+        let color_index = lo | (hi << 1);
         // return pixel color
         match self.mode {
             Mode::GB => (self.pal.bg_color(color_index as usize), color_index),
             Mode::CGB => {
                 let palette = (flags & 0x7) as usize;
-                let color = self.color_pal.bg_pal_color(palette, color_index as usize);
-
-                // tile priority over all OAM
-                // in CGB mode, this tile voerrides any priorities.
-                // TODO don't use magic value (4)
-                if flags & 0x80 != 0 && color_index != 0 {
-                    color_index = 4;
-                }
-
-                (color, color_index)
+                (self.color_pal.bg_pal_color(palette, color_index as usize), color_index)
             }
         }
     }
 
-    fn draw_bg(&mut self, ly: u8, offset: usize, dots: usize) {
-        let Scroll { scx, scy } = self.scroll;
-
-        for lcd_x in offset..(offset + dots) {
-            let y = ly.wrapping_add(scy) as usize;
-            let x = (lcd_x as u8).wrapping_add(scx) as usize;
-            let map = self.lcdc_stat.bg_tile_map();
-            let data = self.lcdc_stat.bg_win_tile_data();
-            let (color, index) = self.point_color(y, x, map, data);
-            self.buffer[ly as usize][lcd_x] = color;
-            self.color_index[ly as usize][lcd_x] = index;
+    // Mode 2 dot. Each OAM entry takes 2 dots to check, and up to 10 sprites overlapping the
+    // current line are selected in OAM order.
+    fn scan_dot(&mut self) {
+        if self.dots == 0 {
+            self.renderer.sprites.clear();
+            if self.line.ly == self.win.wy {
+                self.win_ly = true;
+            }
+        }
+        if self.dots % 2 == 1 && self.renderer.sprites.len() < MAX_SPRITES {
+            let oam = (self.dots / 2) as usize;
+            let entry = *self.oam.get(oam);
+            if entry.on_line(self.line.ly, self.lcdc_stat.lcdc_ob_size()) {
+                self.renderer.sprites.push(Sprite { oam: oam as u8, entry });
+            }
         }
     }
 
-    fn draw_win(&mut self, ly: u8, offset: usize, dots: usize) {
-        assert_eq!(0, offset);
-        assert_eq!(LCD_WIDTH, dots);
+    // Beginning of mode 3.
+    fn start_line(&mut self) {
+        self.renderer.reset(self.scroll.scx);
+    }
 
-        let Window { wy, wx } = self.win;
+    // Mode 3 dot: the fetcher pushes pixels into the BG FIFO and one pixel is shifted out of the
+    // FIFOs to the LCD. Fetching a sprite or starting the window stalls the pipeline, which makes
+    // mode 3 longer.
+    fn render_dot(&mut self) {
+        let lcdc = self.lcdc_stat.lcdc;
+        let x_priority = self.mode == Mode::GB || self.opri & 0x1 != 0;
+        let r = &mut self.renderer;
+
+        if let Some((sprite, dots)) = r.sprite {
+            if dots > 1 {
+                r.sprite = Some((sprite, dots - 1));
+            } else {
+                r.sprite = None;
+                self.fetch_sprite(sprite);
+            }
+            return;
+        }
 
-        if ly < wy {
+        // Window start, once LY has matched WY in the current frame and the next pixel is at
+        // WX-7. With WX < 7 the first pixels of the window are discarded. With WX=0 the fine scroll
+        // of the BG applies to the window as well, so it stutters horizontally with SCX.
+        let wx = self.win.wx;
+        if !r.window && lcdc & 0x20 != 0 && self.win_ly {
+            if r.lx == 0 && self.win_next {
+                r.start_window();
+                r.discard = 0;
+            } else if r.lx as u16 + 7 >= wx as u16 {
+                r.start_window();
+                if r.lx == 0 {
+                    r.discard = if wx == 0 { 7 + (self.scroll.scx & 0x7) } else { 7 - wx };
+                }
+            }
+        }
+
+        if r.delay > 0 {
+            r.delay -= 1;
             return;
         }
 
-        // The window becomes visible (if enabled) when positions are set in
-        // range WX=0..166, WY=0..143. A position of WX=7, WY=0 locates the
-        // window at upper left, it is then completely covering normal
-        // background.
-        let wx = (wx as i16 - 7).max(0);
-
-        for lcd_x in wx..LCD_WIDTH as i16 {
-            let y = (ly as i16 - wy as i16) as usize;
-            let x = (lcd_x - wx) as usize;
-            let map = self.lcdc_stat.win_tile_map();
-            let data = self.lcdc_stat.bg_win_tile_data();
-            let (color, index) = self.point_color(y, x, map, data);
-            self.buffer[ly as usize][lcd_x as usize] = color;
-            self.color_index[ly as usize][lcd_x as usize] = index;
+        // Sprite fetch. The fetcher waits until the current BG tile has been fetched, unless a
+        // sprite was already fetched at this pixel. Several sprites may be due at the first
+        // pixel; with X priority they are fetched by X then OAM index, so the lowest X wins.
+        if lcdc & 0x2 != 0 && r.discard == 0 {
+            let lx = r.lx;
+            // Sprites left behind while objects were disabled are never fetched.
+            r.sprites.retain(|s| s.entry.xpos >= lx);
+            let mut due = r.sprites.iter().enumerate().filter(|(_, s)| s.entry.xpos <= lx + 8);
+            let next = if x_priority {
+                due.min_by_key(|(_, s)| (s.entry.xpos, s.oam))
+            } else {
+                due.next()
+            };
+            if let Some((i, _)) = next {
+                let sprite = r.sprites.remove(i);
+                let mut dots = 6;
+                if r.sprite_x != Some(lx) {
+                    let x = if r.window {
+                        lx.wrapping_add(7).wrapping_sub(wx)
+                    } else {
+                        lx.wrapping_add(self.scroll.scx)
+                    };
+                    dots += 5 - (x & 0x7).min(5);
+                }
+                r.sprite_x = Some(lx);
+                r.sprite = Some((sprite, dots - 1));
+                return;
+            }
+        }
+
+        self.fetch_dot();
+
+        let r = &mut self.renderer;
+        if let Some(bg) = r.bg.pop_front() {
+            if r.discard > 0 {
+                r.discard -= 1;
+            } else {
+                let ob = r.ob.pop_front();
+                let lx = r.lx as usize;
+                r.lx += 1;
+                self.buffer[self.line.ly as usize][lx] = self.mix(bg, ob);
+            }
         }
     }
 
-    #[rustfmt::skip]
-    fn draw_ob(&mut self, ly: u8, offset: usize, dots: usize) {
-        assert_eq!(0, offset);
-        assert_eq!(LCD_WIDTH, dots);
-
-        let ly = ly as i16;
-        let h = self.lcdc_stat.lcdc_ob_size() as i16;
-
-        // TODO fix OAM search
-        //  I should be checking all 40 sprites since only 10 are visible per line.
-        //  The timming of the PPU is somewhat broken so it crashes often.
-        for entry in 0..40 {
-            let Entry { ypos, xpos, mut tile, flags } = *self.oam.get(39 - entry);
-
-            // position of the top-left corner of the sprite within the lcd display.
-            // This value is signed because when xpos=0 the sprite is offscreen (same for ypos).
-            let x = xpos as i16 - 8;
-            let y = ypos as i16 - 16;
-
-            // skip entry if it doesn't overlap with the current line
-            // TODO Again, this line shouldn't be necessary if the above TODO comment was resolved.
-            //  This check should be performed by the OAM search (see method `oam::search`)
-            if ly < y || ly >= y + h {
-                continue;
+    // BG/window fetcher dot.
+    fn fetch_dot(&mut self) {
+        let mut fetcher = self.renderer.fetcher;
+        fetcher.dots += 1;
+        match fetcher.dots {
+            2 => {
+                let (map, y, x) = if self.renderer.window {
+                    (self.lcdc_stat.win_tile_map(), self.win_line, fetcher.x)
+                } else {
+                    let Scroll { scy, scx } = self.scroll;
+                    (self.lcdc_stat.bg_tile_map(), self.line.ly.wrapping_add(scy), (scx >> 3).wrapping_add(fetcher.x))
+                };
+                let idx = map as usize - 0x8000 + 32 * (y as usize / 8) + (x as usize & 0x1f);
+                fetcher.tile = self.vram.bank(0)[idx];
+                fetcher.attr = match self.mode {
+                    Mode::GB => 0,
+                    Mode::CGB => self.vram.bank(1)[idx],
+                };
             }
-            // In 16-pixel mode, the top sprite low bit is always 0 and in the bottom sprite it's 1
-            // Initially I thought this should be handled by game code but later I found that some games
-            // rely on the PPU performing this AND explicitly.
-            //
-            // The games that I found rely on this:
-            //  - Shantae (sprites break otherwise)
-            if h == 16 {
-                tile &= 0xfe;
+            4 => fetcher.lo = self.fetch_tile_data(&fetcher, 0),
+            6 => fetcher.hi = self.fetch_tile_data(&fetcher, 1),
+            7.. if self.renderer.bg.is_empty() => {
+                let flip = fetcher.attr & 0x20 != 0;
+                for col in 0..8 {
+                    let bit = if flip { col } else { 7 - col };
+                    let color = (fetcher.lo >> bit) & 0x1 | ((fetcher.hi >> bit) & 0x1) << 1;
+                    self.renderer.bg.push_back(Pixel { color,
+                                                       palette: fetcher.attr & 0x7,
+                                                       priority: fetcher.attr & 0x80 != 0,
+                                                       ..Pixel::default() });
+                }
+                fetcher.dots = 0;
+                fetcher.x = fetcher.x.wrapping_add(1);
             }
+            _ => {}
+        }
+        self.renderer.fetcher = fetcher;
+    }
 
-            for lcd_x in x.max(0)..(x + 8).min(LCD_WIDTH as _) {
-                // pixel position within the tile
-                let mut row = (ly - y) as usize;
-                let mut col = 7 - (lcd_x - x) as usize;
-                // flip/mirror sprite
-                if flags & 0x40 != 0 { row = (h as usize - 1) - row }
-                if flags & 0x20 != 0 { col = 7 - col }
-                // pretty much the same as in Self::point_color
-                let offset = 16 * (tile as usize) + row * 2;
-                let bank = match self.mode {
-                    Mode::GB => 0,
-                    Mode::CGB => (flags >> 3) & 0x1,
-                } as usize;
-                let lo = self.vram.bank(bank)[offset] >> (col as u8) & 0x1;
-                let hi = self.vram.bank(bank)[offset + 1] >> (col as u8) & 0x1;
-                let color_index = lo | (hi << 1);
+    // Low (byte 0) or high (byte 1) byte of the row of the tile being fetched.
+    fn fetch_tile_data(&self, fetcher: &Fetcher, byte: usize) -> u8 {
+        let y = if self.renderer.window {
+            self.win_line
+        } else {
+            self.line.ly.wrapping_add(self.scroll.scy)
+        };
+        let mut row = y as usize & 0x7;
+        if fetcher.attr & 0x40 != 0 {
+            row = 7 - row;
+        }
+        let bank = (fetcher.attr >> 3) as usize & 0x1;
+        let offset = tile_data_offset(self.lcdc_stat.bg_win_tile_data(), fetcher.tile);
+        self.vram.bank(bank)[offset + row * 2 + byte]
+    }
 
-                // discards transparent pixels (color_index = 0)
-                // works out tile priority and decide if the sprite pixel is discarded (begind BG)
-                // there are a few cases:
-                //  - color index 0 is transparent (always discarded)
-                //  - If one of the LCDC bits is set and the color index underneath is not 0.
-                //  - (CGB only) The BG tile underneath can override any priorities (always discard).
-                if color_index == 0 || // transparent pixel
-                    flags & 0x80 != 0 && self.color_index[ly as usize][lcd_x as usize] != 0 || // behind colors 1-3
-                    self.color_index[ly as usize][lcd_x as usize] == 4 // bg tile OAM priority bits (CGB only)
-                {
-                    continue;
-                }
+    fn fetch_sprite(&mut self, sprite: Sprite) {
+        let Entry { ypos, xpos, mut tile, flags } = sprite.entry;
+        let h = self.lcdc_stat.lcdc_ob_size() as usize;
+        // In 16-pixel mode, the top sprite low bit is always 0 and in the bottom sprite it's 1
+        // Initially I thought this should be handled by game code but later I found that some games
+        // rely on the PPU performing this AND explicitly.
+        //
+        // The games that I found rely on this:
+        //  - Shantae (sprites break otherwise)
+        if h == 16 {
+            tile &= 0xfe;
+        }
+        // row within the sprite. The height may have changed since the OAM scan.
+        let mut row = ((self.line.ly as usize + 16) - ypos as usize) & (h - 1);
+        if flags & 0x40 != 0 {
+            row = h - 1 - row;
+        }
+        let bank = match self.mode {
+            Mode::GB => 0,
+            Mode::CGB => (flags >> 3) & 0x1,
+        } as usize;
+        let offset = 16 * (tile as usize) + row * 2;
+        let lo = self.vram.bank(bank)[offset];
+        let hi = self.vram.bank(bank)[offset + 1];
+        let palette = match self.mode {
+            Mode::GB => (flags >> 4) & 0x1,
+            Mode::CGB => flags & 0x7,
+        };
+        let mut pixels = [Pixel::default(); 8];
+        for (col, pixel) in pixels.iter_mut().enumerate() {
+            let bit = if flags & 0x20 != 0 { col } else { 7 - col };
+            *pixel = Pixel { color: (lo >> bit) & 0x1 | ((hi >> bit) & 0x1) << 1,
+                             palette,
+                             priority: flags & 0x80 != 0,
+                             oam: sprite.oam };
+        }
+        // sprites partially off the left edge of the screen
+        let offset = (self.renderer.lx as usize + 8) - xpos as usize;
+        // DMG and CGB in DMG compatibility mode use X priority.
+        let oam_priority = self.mode == Mode::CGB && self.opri & 0x1 == 0;
+        self.renderer.mix_sprite(pixels, offset, oam_priority);
+    }
 
-                // draw pixel color
-                self.buffer[ly as usize][lcd_x as usize] = match self.mode {
-                    Mode::GB => {
-                        let pal = (flags >> 4 & 0x1) as usize;
-                        self.pal.obp_color(pal, color_index as usize)
+    // Mixes a BG pixel with the OB pixel on top of it.
+    //
+    // Sprite pixels are discarded when:
+    //  - color index 0 is transparent (always discarded)
+    //  - The sprite priority flag is set and the BG color index is not 0.
+    //  - (CGB only) The BG tile attributes priority flag is set and the BG color index is not 0.
+    //    LCDC bit 0 overrides both priority flags in CGB mode.
+    fn mix(&self, bg: Pixel, ob: Option<Pixel>) -> Color {
+        let lcdc = self.lcdc_stat.lcdc;
+        let ob = ob.filter(|ob| lcdc & 0x2 != 0 && ob.color != 0);
+        match self.mode {
+            Mode::GB => {
+                // Behavior of the LCD registers is different on each game boy model.
+                // In GB mode, LCDC bit 0 blanks the BG and window.
+                let color = if lcdc & 0x1 != 0 { bg.color } else { 0 };
+                match ob {
+                    Some(ob) if !ob.priority || color == 0 => {
+                        self.pal.obp_color(ob.palette as usize, ob.color as usize)
                     }
-                    Mode::CGB => {
-                        let palette = (flags & 0x7) as usize;
-                        self.color_pal.ob_pal_color(palette, color_index as usize)
-                    },
-                };
+                    _ => self.pal.bg_color(color as usize),
+                }
             }
+            Mode::CGB => match ob {
+                Some(ob) if lcdc & 0x1 == 0 || bg.color == 0 || !(bg.priority || ob.priority) => {
+                    self.color_pal.ob_pal_color(ob.palette as usize, ob.color as usize)
+                }
+                _ => self.color_pal.bg_pal_color(bg.palette as usize, bg.color as usize),
+            },
         }
     }
 }
 
-// FIXME parts of the PPU are not accessible deppending on the current state,
-//  but these gates are commented out due to timming bugs.
+// Offset of a tile within the tile data.
+fn tile_data_offset(data: TileDataAddr, tile: u8) -> usize {
+    match data {
+        TileDataAddr::X8000 => 16 * (tile as usize),
+        TileDataAddr::X8800 => (0x1000 + 16 * (tile as i8 as isize)) as usize,
+    }
+}
+
+// Parts of the PPU are not accessible depending on the current mode (see `Ppu::set_permissive`).
 impl<V: Video> Device for Ppu<V> {
     fn read(&self, addr: u16) -> u8 {
         match addr {
+            0x8000..=0x9fff | 0xff69 | 0xff6b if self.vram_blocked() => 0xff,
+            0xfe00..=0xfe9f if self.oam_blocked() => 0xff,
             0x8000..=0x9fff => self.vram.read(addr),
-            0xfe00..=0xfe9f => {
-                // if matches!(self.stat_mode, StatMode::HBlank | StatMode::VBlank) {
-                self.oam.read(addr)
-                // } else {
-                //     0
-                // }
-            }
+            0xfe00..=0xfe9f => self.oam.read(addr),
             0xff40 | 0xff41 => self.lcdc_stat.read(addr),
             0xff42 | 0xff43 => self.scroll.read(addr),
             0xff44 => self.line.ly,
             0xff45 => self.line.lyc,
             0xff4a | 0xff4b => self.win.read(addr),
             0xff47..=0xff49 => self.pal.read(addr),
-            0xff4f => {
-                // if matches!(
-                //     self.stat_mode,
-                //     StatMode::Search | StatMode::HBlank | StatMode::VBlank
-                // ) {
-                self.vram.read(addr)
-                // } else {
-                //     0
-                // }
-            }
-            0xff68..=0xff6b => {
-                // if matches!(
-                //     self.stat_mode,
-                //     StatMode::Search | StatMode::HBlank | StatMode::VBlank
-                // ) {
-                self.color_pal.read(addr)
-                // } else {
-                //     0
-                // }
-            }
+            0xff4f => self.vram.read(addr),
+            0xff68..=0xff6b => self.color_pal.read(addr),
+            0xff6c => self.opri | 0xfe,
             _ => panic!(),
         }
     }
 
     fn write(&mut self, addr: u16, data: u8) {
         match addr {
+            0x8000..=0x9fff if self.vram_blocked() => {}
+            0xfe00..=0xfe9f if self.oam_blocked() => {}
+            // the palette index is incremented all the same
+            0xff69 | 0xff6b if self.vram_blocked() => self.color_pal.skip_write(addr),
             0x8000..=0x9fff => self.vram.write(addr, data),
-            0xfe00..=0xfe9f => {
-                // if matches!(self.stat_mode, StatMode::HBlank | StatMode::VBlank) {
-                self.oam.write(addr, data)
-                // }
-            }
+            0xfe00..=0xfe9f => self.oam.write(addr, data),
             0xff40 | 0xff41 => {
                 let lcdc = self.lcdc_stat.lcdc;
+
+                // DMG bug: the mode 0, mode 1 and LY=LYC interrupt sources are enabled for one
+                // cycle when STAT is written, which requests an interrupt during modes 0 and 1 or
+                // when LY=LYC.
+                if addr == 0xff41 && self.mode == Mode::GB && lcdc & 0x80 != 0 {
+                    self.lcdc_stat.write(addr, 0x58);
+                    self.update_stat();
+                }
                 self.lcdc_stat.write(addr, data);
 
                 // LCD display disabled
@@ -527,10 +695,23 @@ impl<V: Video> Device for Ppu<V> {
                     self.line.ly = 0;
                     self.stat_mode = StatMode::HBlank;
                     self.lcdc_stat.stat_set_mode(self.stat_mode);
+                    self.stat_irq = false;
 
                     // clear video output
                     self.clear_video();
                 }
+
+                // LCD display enabled, starting from line 0
+                if lcdc & 0x80 == 0 && self.lcdc_stat.lcdc & 0x80 != 0 {
+                    self.reset_window();
+                    self.dots = 0;
+                    self.stat_mode = StatMode::Search;
+                }
+
+                // enabling a source while its condition holds requests an interrupt
+                if self.lcdc_stat.lcdc & 0x80 != 0 {
+                    self.update_stat();
+                }
             }
             0xff42 | 0xff43 => self.scroll.write(addr, data),
             0xff44 => {
@@ -541,7 +722,12 @@ impl<V: Video> Device for Ppu<V> {
                 self.stat_mode = StatMode::Search;
                 self.lcdc_stat.stat_set_mode(self.stat_mode);
             }
-            0xff45 => self.line.lyc = data,
+            0xff45 => {
+                self.line.lyc = data;
+                if self.lcdc_stat.lcdc & 0x80 != 0 {
+                    self.update_stat();
+                }
+            }
             0xff4a | 0xff4b => {
                 if addr == 0xff4a && data != 0 {
                     //eprintln!("WY = {}, LY = {}", data, self.line.ly);
@@ -549,22 +735,9 @@ impl<V: Video> Device for Ppu<V> {
                 self.win.write(addr, data)
             }
             0xff47..=0xff49 => self.pal.write(addr, data),
-            0xff4f => {
-                // if matches!(
-                //     self.stat_mode,
-                //     StatMode::Search | StatMode::HBlank | StatMode::VBlank
-                // ) {
-                self.vram.write(addr, data)
-                // }
-            }
-            0xff68..=0xff6b => {
-                // if matches!(
-                //     self.stat_mode,
-                //     StatMode::Search | StatMode::HBlank | StatMode::VBlank
-                // ) {
-                self.color_pal.write(addr, data)
-                // }
-            }
+            0xff4f => self.vram.write(addr, data),
+            0xff68..=0xff6b => self.color_pal.write(addr, data),
+            0xff6c => self.opri = data & 0x1,
             _ => panic!(),
         }
     }
@@ -0,0 +1,120 @@
+use crate::ppu::ppu::oam::Entry;
+use std::collections::VecDeque;
+
+// Dots taken by the first tile fetch of the line, which is discarded.
+const FIRST_FETCH: u8 = 6;
+
+/// Sprites selected per line by the OAM scan.
+pub(crate) const MAX_SPRITES: usize = 10;
+
+/// A pixel waiting in one of the FIFOs.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Pixel {
+    /// Color index (0-3). Index 0 is transparent for sprites.
+    pub color: u8,
+    /// BG: CGB palette. OB: OBP0/OBP1 in GB mode, or CGB palette.
+    pub palette: u8,
+    /// BG: CGB tile attribute priority. OB: sprite is behind BG colors 1-3.
+    pub priority: bool,
+    /// OB: index of the sprite within OAM.
+    pub oam: u8,
+}
+
+/// Sprite selected by the OAM scan.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Sprite {
+    /// Index within OAM.
+    pub oam: u8,
+    pub entry: Entry,
+}
+
+/// BG and window tile fetcher.
+///
+/// A fetch takes 2 dots for each of the tile number, the low and the high byte of the tile
+/// row. The 8 pixels are then pushed as soon as the BG FIFO is empty.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Fetcher {
+    /// Dots elapsed since the beginning of the current fetch.
+    pub dots: u8,
+    /// Tile column being fetched, relative to the first one of the line (or of the window).
+    pub x: u8,
+    pub tile: u8,
+    /// CGB BG map attributes.
+    pub attr: u8,
+    pub lo: u8,
+    pub hi: u8,
+}
+
+/// Mode 3 state of the current line.
+pub(crate) struct Renderer {
+    pub bg: VecDeque<Pixel>,
+    pub ob: VecDeque<Pixel>,
+    pub fetcher: Fetcher,
+    /// Next pixel to be output.
+    pub lx: u8,
+    /// Pixels left to be shifted out and discarded (SCX fine scroll, WX < 7).
+    pub discard: u8,
+    /// Dots left before the fetcher starts.
+    pub delay: u8,
+    /// Fetching window tiles.
+    pub window: bool,
+    /// Sprites selected by the OAM scan of the current line, not fetched yet.
+    pub sprites: Vec<Sprite>,
+    /// Sprite being fetched, and the dots left until it is mixed into the OB FIFO.
+    pub sprite: Option<(Sprite, u8)>,
+    /// Pixel at which the last sprite was fetched.
+    pub sprite_x: Option<u8>,
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        Self { bg: VecDeque::with_capacity(16),
+               ob: VecDeque::with_capacity(8),
+               fetcher: Fetcher::default(),
+               lx: 0,
+               discard: 0,
+               delay: 0,
+               window: false,
+               sprites: Vec::with_capacity(MAX_SPRITES),
+               sprite: None,
+               sprite_x: None }
+    }
+}
+
+impl Renderer {
+    /// Resets the state for mode 3. Sprites are selected beforehand, during mode 2.
+    pub fn reset(&mut self, scx: u8) {
+        self.bg.clear();
+        self.ob.clear();
+        self.fetcher = Fetcher::default();
+        self.lx = 0;
+        self.discard = scx & 0x7;
+        self.delay = FIRST_FETCH;
+        self.window = false;
+        self.sprite = None;
+        self.sprite_x = None;
+    }
+
+    /// Restarts the fetcher on the first window tile.
+    pub fn start_window(&mut self) {
+        self.bg.clear();
+        self.fetcher = Fetcher::default();
+        self.window = true;
+    }
+
+    /// Mixes 8 sprite pixels into the OB FIFO, starting at `offset` pixels within the sprite.
+    ///
+    /// Sprites are fetched from left to right, so when `oam_priority` is false existing opaque
+    /// pixels take priority (lowest X, then lowest OAM index). Otherwise the pixel of the sprite
+    /// with the lowest OAM index is kept (CGB).
+    pub fn mix_sprite(&mut self, pixels: [Pixel; 8], offset: usize, oam_priority: bool) {
+        for (i, pixel) in pixels[offset..].iter().enumerate() {
+            match self.ob.get_mut(i) {
+                Some(old) if old.color == 0 => *old = *pixel,
+                Some(old) if oam_priority && pixel.color != 0 && pixel.oam < old.oam => *old = *pixel,
+                Some(_) => {}
+                None => self.ob.push_back(*pixel),
+            }
+        }
+    }
+}
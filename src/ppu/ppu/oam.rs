@@ -30,12 +30,15 @@ impl Default for Oam {
     }
 }
 
-impl Oam {
-
-
-    pub(crate) fn visible(&self) -> impl Iterator<Item = &Entry> {
-        self.entries.iter()
+impl Entry {
+    /// Returns true if the sprite overlaps line `ly`, for sprites of height `h` (8 or 16).
+    pub fn on_line(&self, ly: u8, h: u8) -> bool {
+        let y = u16::from(ly) + 16;
+        y >= u16::from(self.ypos) && y < u16::from(self.ypos) + u16::from(h)
     }
+}
+
+impl Oam {
 
     /// Returns an iterator over the 40 OAM entries.
     pub fn iter(&self) -> impl Iterator<Item = &Entry> {
@@ -248,6 +248,16 @@ impl ColorPal {
     pub fn clear_color(&self) -> Color {
         [0xff, 0xff, 0xff]
     }
+
+    // Increments BGPI or OBPI (when auto-increment is set) for a write to BGPD or OBPD that
+    // was ignored.
+    pub(crate) fn skip_write(&mut self, addr: u16) {
+        match addr {
+            0xff69 => self.bgpi = next_color_pal_index(self.bgpi),
+            0xff6b => self.obpi = next_color_pal_index(self.obpi),
+            _ => panic!(),
+        }
+    }
 }
 
 impl Device for ColorPal {
@@ -273,8 +283,12 @@ impl Device for ColorPal {
 }
 
 
-fn write_color_pal(pal: &mut [u8], mut idx: u8, data: u8) -> u8 {
+fn write_color_pal(pal: &mut [u8], idx: u8, data: u8) -> u8 {
     pal[(idx & 0x3f) as usize] = data;
+    next_color_pal_index(idx)
+}
+
+fn next_color_pal_index(mut idx: u8) -> u8 {
     if idx & 0x80 != 0 {
         idx += 1;
         idx &= 0xbf;
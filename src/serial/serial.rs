@@ -0,0 +1,95 @@
+use crate::{clock::clock::Clock, device::device::Device, interrupt::interrupt::Flag, Mode, CLOCK};
+
+/// Serial port (link cable).
+///
+/// Nothing is ever connected to the other end: transfers driven by the internal clock shift
+/// in 0xff, and transfers waiting for an external clock never complete. Every byte sent is
+/// kept in an output buffer, which is how test ROMs report their results.
+///
+/// In CGB mode, SC bit 1 selects the fast internal clock (262144 Hz instead of 8192 Hz).
+pub struct Serial {
+    mode: Mode,
+    sb: u8,
+    sc: u8,
+    // Bits left to shift in the current transfer.
+    bits: u8,
+    clock: Clock,
+    output: Vec<u8>,
+    serial_int: Option<Flag>,
+}
+
+impl Serial {
+    pub(crate) fn new(mode: Mode) -> Self {
+        Self { mode,
+               sb: 0,
+               sc: 0,
+               bits: 0,
+               clock: Clock::new(CLOCK, 8_192),
+               output: Vec::new(),
+               serial_int: None }
+    }
+
+    pub fn step(&mut self, cycles: u64) {
+        if self.bits == 0 {
+            return;
+        }
+        for _ in 0..self.clock.step(cycles) {
+            self.sb = (self.sb << 1) | 1;
+            self.bits -= 1;
+            if self.bits == 0 {
+                self.sc &= 0x7f;
+                self.serial_int = Some(Flag::Serial);
+                break;
+            }
+        }
+    }
+
+    /// Returns the bytes sent so far.
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// Returns the bytes sent so far and clears the output buffer.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    pub(crate) fn take_serial_int(&mut self) -> Option<Flag> {
+        self.serial_int.take()
+    }
+
+    // Bits of SC that are readable and writable.
+    fn sc_mask(&self) -> u8 {
+        match self.mode {
+            Mode::GB => 0x81,
+            Mode::CGB => 0x83,
+        }
+    }
+}
+
+impl Device for Serial {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xff01 => self.sb,
+            0xff02 => self.sc | !self.sc_mask(),
+            _ => panic!(),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0xff01 => self.sb = data,
+            0xff02 => {
+                self.sc = data & self.sc_mask();
+                // Start a transfer with the internal clock.
+                if data & 0x81 == 0x81 {
+                    let freq = if self.sc & 0x02 != 0 { 262_144 } else { 8_192 };
+                    self.output.push(self.sb);
+                    self.bits = 8;
+                    self.clock = Clock::new(CLOCK, freq);
+                }
+            }
+            _ => panic!(),
+        }
+    }
+}
@@ -1,34 +1,32 @@
-use crate::{clock::clock::Clock, device::device::Device, interrupt::interrupt::Flag, CLOCK};
+use crate::{device::device::Device, interrupt::interrupt::Flag};
 
 /// DMG timer emulation.
+///
+/// The timer is driven by a 16-bit system counter that advances on every CPU cycle, and DIV is
+/// its upper byte. TIMA is incremented on the falling edge of the counter bit selected by TAC
+/// (ANDed with the TAC enable bit), so resetting DIV or changing TAC may increment TIMA too.
+///
+/// When TIMA overflows it reads 0x00 for one M-cycle. TIMA is reloaded from TMA and the
+/// interrupt is requested on the next M-cycle. Writing TIMA during the first M-cycle cancels
+/// the reload and the interrupt; writing TIMA during the reload M-cycle has no effect, and
+/// writing TMA during it is copied to TIMA as well.
+#[derive(Default)]
 pub struct Timer {
-    div: u8,
-    div_clock: Clock,
+    counter: u16,
     tima: u8,
     tma: u8,
     tac: u8,
-    tima_clock: Clock,
+    // TIMA overflowed during the current M-cycle, and is reloaded on the next one.
+    overflow: bool,
+    // TIMA was reloaded from TMA during the current M-cycle.
+    reloading: bool,
     tima_int: Option<Flag>,
 }
 
-impl Default for Timer {
-    fn default() -> Self {
-        Self { div: 0,
-               div_clock: Clock::new(CLOCK, 16_384),
-               tima: 0,
-               tma: 0,
-               tac: 0,
-               tima_int: None,
-               tima_clock: Clock::new(0, 0) }
-    }
-}
-
 impl Timer {
     pub fn step(&mut self, cycles: u64) {
-        self.step_div(cycles);
-
-        if self.tac & 0x4 != 0 {
-            self.step_tima(cycles);
+        for _ in 0..cycles / 4 {
+            self.tick();
         }
     }
 
@@ -36,18 +34,37 @@ impl Timer {
         self.tima_int.take()
     }
 
-    fn step_div(&mut self, cycles: u64) {
-        let div_cycles = self.div_clock.step(cycles);
-        self.div = self.div.wrapping_add(div_cycles as u8);
+    // Advances the timer by one M-cycle.
+    fn tick(&mut self) {
+        self.reloading = false;
+        if self.overflow {
+            self.overflow = false;
+            self.reloading = true;
+            self.tima = self.tma;
+            self.tima_int = Some(Flag::Timer);
+        }
+        let signal = self.signal();
+        self.counter = self.counter.wrapping_add(4);
+        self.detect_edge(signal);
     }
 
-    fn step_tima(&mut self, cycles: u64) {
-        for _ in 0..self.tima_clock.step(cycles) {
-            self.tima = self.tima.wrapping_add(1);
-            if self.tima == 0 {
-                self.tima = self.tma;
-                self.tima_int = Some(Flag::Timer);
-            }
+    // State of the counter bit selected by TAC, ANDed with the enable bit.
+    fn signal(&self) -> bool {
+        let bit = match self.tac & 0x3 {
+            0 => 9,
+            1 => 3,
+            2 => 5,
+            _ => 7,
+        };
+        self.tac & 0x4 != 0 && self.counter & (1 << bit) != 0
+    }
+
+    // Increments TIMA if the signal went from high to low.
+    fn detect_edge(&mut self, before: bool) {
+        if before && !self.signal() {
+            let (tima, overflow) = self.tima.overflowing_add(1);
+            self.tima = tima;
+            self.overflow = overflow;
         }
     }
 }
@@ -55,7 +72,7 @@ impl Timer {
 impl Device for Timer {
     fn read(&self, addr: u16) -> u8 {
         match addr {
-            0xff04 => self.div,
+            0xff04 => (self.counter >> 8) as u8,
             0xff05 => self.tima,
             0xff06 => self.tma,
             0xff07 => self.tac,
@@ -65,24 +82,29 @@ impl Device for Timer {
 
     fn write(&mut self, addr: u16, data: u8) {
         match addr {
-            0xff04 => self.div = 0,
-            0xff05 => self.tima = data,
-            0xff06 => self.tma = data,
-
+            0xff04 => {
+                let signal = self.signal();
+                self.counter = 0;
+                self.detect_edge(signal);
+            }
+            0xff05 => {
+                if !self.reloading {
+                    self.tima = data;
+                    self.overflow = false;
+                }
+            }
+            0xff06 => {
+                self.tma = data;
+                if self.reloading {
+                    self.tima = data;
+                }
+            }
             0xff07 => {
+                let signal = self.signal();
                 self.tac = data;
-                let freq = match self.tac & 0x3 {
-                    0 => 4_096,
-                    1 => 262_144,
-                    2 => 65_536,
-                    3 => 16_384,
-                    _ => panic!(),
-                };
-                self.tima_clock = Clock::new(CLOCK, freq);
+                self.detect_edge(signal);
             }
             _ => panic!(),
         }
     }
 }
-
-
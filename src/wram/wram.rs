@@ -3,7 +3,8 @@ use crate::device::device::Device;
 const SIZE: usize = 0x1000;
 
 /// Work-RAM emulation.
-/// Behavior deppends on the emulation mode (GB or CGB).
+/// Banks are switched through SVBK, which is only mapped in CGB mode. In GB mode 0xd000-0xdfff
+/// is always bank 1.
 pub struct WRam {
     svbk: u8,
     wram: Box<[[u8; SIZE]; 8]>,
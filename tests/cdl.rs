@@ -0,0 +1,60 @@
+//! Code/data log tests.
+use emulator::{
+    cartridge,
+    mmu::mmu::cdl::{Cdl, CODE, DATA, WRITE},
+    Builder,
+};
+
+#[test]
+fn code_data_and_writes() {
+    let mut rom = vec![0; 0x8000];
+    #[rustfmt::skip]
+    let code = [
+        0xfa, 0x00, 0x50, // LD A,($5000)
+        0xea, 0x00, 0xc0, // LD ($C000),A
+        0x18, 0xfe,       // JR $0106
+    ];
+    rom[0x100..0x100 + code.len()].copy_from_slice(&code);
+
+    let cartridge = cartridge::from_bytes(&rom).unwrap();
+    let mut gb = Builder::default().cartridge(cartridge).gb_mode().skip_boot().build();
+    gb.mmu_mut().set_cdl(Some(Cdl::new(rom.len())));
+    for _ in 0..3 {
+        gb.step();
+    }
+
+    let cdl = gb.mmu().cdl().unwrap();
+    assert!(cdl.rom()[0x100..0x108].iter().all(|&f| f == CODE));
+    assert_eq!(cdl.rom()[0x5000], DATA);
+    assert_eq!(cdl.ram()[0xc000 - 0x8000], WRITE);
+    assert_eq!(cdl.rom_stats(), (8, 1, 0x8000 - 9));
+}
+
+#[test]
+fn mbc_writes_not_logged() {
+    // MBC1, 64KiB.
+    let mut rom = vec![0; 0x10000];
+    rom[0x147] = 0x01;
+    rom[0x148] = 0x01;
+    #[rustfmt::skip]
+    let code = [
+        0x3e, 0x02,       // LD A,$02
+        0xea, 0x00, 0x20, // LD ($2000),A
+        0xaf,             // XOR A
+        0xea, 0x00, 0x40, // LD ($4000),A
+        0xea, 0x00, 0xc0, // LD ($C000),A
+    ];
+    rom[0x100..0x100 + code.len()].copy_from_slice(&code);
+
+    let cartridge = cartridge::from_bytes(&rom).unwrap();
+    let mut gb = Builder::default().cartridge(cartridge).gb_mode().skip_boot().build();
+    gb.mmu_mut().set_cdl(Some(Cdl::new(rom.len())));
+    for _ in 0..5 {
+        gb.step();
+    }
+
+    let cdl = gb.mmu().cdl().unwrap();
+    assert!(cdl.rom()[0x100..0x10c].iter().all(|&f| f == CODE));
+    assert!(cdl.rom().iter().all(|&f| f & WRITE == 0));
+    assert_eq!(cdl.ram()[0xc000 - 0x8000], WRITE);
+}
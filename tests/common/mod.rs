@@ -0,0 +1,54 @@
+//! Fixture shared by the integration tests.
+#![allow(dead_code)]
+
+use emulator::{
+    cpu::cpu::bus::Bus,
+    device::device::Device,
+    mmu::mmu::Mmu,
+    ppu::ppu::{Ppu, Video},
+    Builder, GameBoy,
+};
+
+/// A system without cartridge, past the boot ROM.
+///
+/// `read`, `write` and `idle` drive the MMU the way the CPU does: every memory access is
+/// preceded by a tick of one M-cycle. `peek` and `poke` access it without advancing time.
+pub struct System<V: Video = ()>(pub GameBoy<(), V, ()>);
+
+impl<V: Video> System<V> {
+    pub fn with(builder: Builder<(), V, ()>) -> Self {
+        Self(builder.skip_boot().build())
+    }
+
+    pub fn mmu(&mut self) -> &mut Mmu<(), V, ()> {
+        self.0.mmu_mut()
+    }
+
+    pub fn ppu(&mut self) -> &mut Ppu<V> {
+        self.0.mmu_mut().ppu_mut()
+    }
+
+    pub fn idle(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            Bus::tick(self.mmu());
+        }
+    }
+
+    pub fn read(&mut self, addr: u16) -> u8 {
+        Bus::tick(self.mmu());
+        Bus::read(self.mmu(), addr)
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        Bus::tick(self.mmu());
+        Bus::write(self.mmu(), addr, data);
+    }
+
+    pub fn peek(&mut self, addr: u16) -> u8 {
+        Device::read(self.mmu(), addr)
+    }
+
+    pub fn poke(&mut self, addr: u16, data: u8) {
+        Device::write(self.mmu(), addr, data)
+    }
+}
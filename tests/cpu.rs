@@ -0,0 +1,108 @@
+//! CPU tests for STOP, the CGB speed switch and interrupt dispatch.
+use emulator::{
+    cartridge::{self, cartridge::Cartridge},
+    device::device::Device,
+    joypad::joypad::{Btn, Key},
+    Builder, GameBoy,
+};
+
+const DIV: u16 = 0xff04;
+const KEY1: u16 = 0xff4d;
+
+type System = GameBoy<Box<dyn Cartridge>, (), ()>;
+type Mode = fn(Builder<(), (), ()>) -> Builder<(), (), ()>;
+
+// Runs `code` at 0x0100, after the boot ROM.
+fn system(code: &[u8], mode: Mode) -> System {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x100 + code.len()].copy_from_slice(code);
+    let cartridge = cartridge::from_bytes(&rom).unwrap();
+    mode(Builder::default()).cartridge(cartridge).skip_boot().build()
+}
+
+#[test]
+fn stop() {
+    for (mode, cgb) in [(Builder::gb_mode as Mode, false), (Builder::gbc_mode, true)] {
+        #[rustfmt::skip]
+        let mut gb = system(&[
+            0x10, 0x00, // $0100: STOP
+            0x00,       // $0102: NOP
+        ], mode);
+        if !cgb {
+            // KEY1 doesn't exist in GB mode, so this can't arm a speed switch
+            gb.mmu_mut().write(KEY1, 0x01);
+            assert_eq!(gb.mmu().read(KEY1), 0xff);
+        }
+        // select the action buttons
+        gb.mmu_mut().write(0xff00, 0x10);
+        gb.step();
+        assert!(gb.cpu().stop());
+        assert_eq!(gb.cpu().reg().pc, 0x0102);
+        for _ in 0..100 {
+            gb.step();
+        }
+        assert!(gb.cpu().stop());
+        assert_eq!(gb.cpu().reg().pc, 0x0102);
+
+        // left when a button is pressed
+        gb.mmu_mut().joypad_mut().press(Key::Btn(Btn::A));
+        gb.step();
+        assert!(!gb.cpu().stop());
+        assert_eq!(gb.cpu().reg().pc, 0x0103);
+    }
+}
+
+#[test]
+fn speed_switch() {
+    #[rustfmt::skip]
+    let mut gb = system(&[
+        0x06, 0x00, // $0100: LD B,$00
+        0x05,       // $0102: DEC B
+        0x20, 0xfd, // $0103: JR NZ,$0102
+        0x10, 0x00, // $0105: STOP
+        0x10, 0x00, // $0107: STOP
+        0x18, 0xfe, // $0109: JR $0109
+    ], Builder::gbc_mode);
+    while gb.cpu().reg().pc != 0x0105 {
+        gb.step();
+    }
+    assert_ne!(gb.mmu().read(DIV), 0x00);
+    assert_eq!(gb.mmu().read(KEY1), 0x7e);
+    gb.mmu_mut().write(KEY1, 0x01);
+    assert_eq!(gb.mmu().read(KEY1), 0x7f);
+
+    // armed through KEY1: switches to double speed and resets DIV instead of stopping
+    gb.step();
+    assert!(!gb.cpu().stop());
+    assert_eq!(gb.cpu().reg().pc, 0x0107);
+    assert_eq!(gb.mmu().read(KEY1), 0xfe);
+    assert_eq!(gb.mmu().read(DIV), 0x00);
+
+    // and back to normal speed
+    gb.mmu_mut().write(KEY1, 0x01);
+    gb.step();
+    assert!(!gb.cpu().stop());
+    assert_eq!(gb.cpu().reg().pc, 0x0109);
+    assert_eq!(gb.mmu().read(KEY1), 0x7e);
+}
+
+#[test]
+fn ie_push() {
+    // The high byte of PC (0x02) lands in IE at 0xffff and leaves only STAT enabled.
+    for (flags, vector) in [(0x01, 0x0000), (0x03, 0x0048)] {
+        let mut gb = system(&[], Builder::gb_mode);
+        gb.cpu_mut().reg_mut().pc = 0x0200;
+        gb.cpu_mut().reg_mut().sp = 0x0000;
+        gb.cpu_mut().set_ime(true);
+        gb.mmu_mut().write(0xffff, 0x01);
+        gb.mmu_mut().write(0xff0f, flags);
+
+        assert_eq!(gb.step(), 20);
+        assert_eq!(gb.cpu().dispatched(), Some(vector));
+        assert_eq!(gb.cpu().reg().pc, vector);
+        assert_eq!(gb.mmu().read(0xffff), 0x02);
+        assert_eq!(gb.mmu().read(0xfffe), 0x00);
+        // a cancelled dispatch doesn't acknowledge the VBlank interrupt
+        assert_eq!(gb.mmu().read(0xff0f), 0xe1);
+    }
+}
@@ -0,0 +1,90 @@
+//! Disassembler tests: control flow tracing and the listing written by `gbdis`.
+use emulator::disasm::{disasm::Disassembler, symbols::Symbols};
+
+// Two banks of code and data. Every byte that is not code is 0x11.
+fn rom() -> Vec<u8> {
+    let mut rom = vec![0x11; 0x8000];
+    #[rustfmt::skip]
+    let code: [(usize, &[u8]); 6] = [
+        (0x0008, &[0xc9]),             // RET
+        (0x0100, &[0x18, 0x0e]),       // JR $0110
+        (0x0110, &[0xcd, 0x00, 0x02,   // CALL $0200
+                   0xcf,               // RST $08
+                   0x20, 0x01,         // JR NZ,$0117
+                   0x76,               // HALT
+                   0xc3, 0x00, 0x40]), // JP $4000
+        (0x0200, &[0xe9]),             // JP (HL)
+        (0x4000, &[0x3e, 0x01,         // LD A,$01
+                   0xc9]),             // RET
+        (0x7ffe, &[0x18, 0xfe]),       // JR $7FFE (not reached)
+    ];
+    for (offset, bytes) in code {
+        rom[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+    rom
+}
+
+#[test]
+fn trace() {
+    let rom = rom();
+    let dis = Disassembler::new(&rom);
+    let trace = dis.trace(&[(0, 0x0100)]);
+
+    let instructions =
+        [0x0008, 0x0100, 0x0110, 0x0113, 0x0114, 0x0116, 0x0117, 0x0200, 0x4000, 0x4002];
+    for offset in 0..rom.len() {
+        assert_eq!(trace.is_instruction(offset), instructions.contains(&offset), "{:04x}", offset);
+    }
+    // JR, CALL, RST, conditional JR and JP targets
+    for offset in [0x0008, 0x0110, 0x0117, 0x0200, 0x4000] {
+        assert!(trace.is_label(offset), "{:04x}", offset);
+    }
+    assert!(trace.is_code(0x0111) && trace.is_code(0x0119) && trace.is_code(0x4001));
+    // after JR, RET and JP (HL)
+    assert!(!trace.is_code(0x0102) && !trace.is_code(0x0009) && !trace.is_code(0x0201));
+    assert!(!trace.is_code(0x4003) && !trace.is_code(0x7ffe));
+}
+
+#[test]
+fn listing() {
+    let rom = rom();
+    let dis = Disassembler::new(&rom);
+    let trace = dis.trace(&[(0, 0x0100)]);
+    let mut symbols = Symbols::new();
+    symbols.insert(1, 0x4000, "Bank1");
+
+    let mut out = Vec::new();
+    dis.dump(&mut out, &trace, &symbols).unwrap();
+    let listing = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = listing.lines().collect();
+    let at = |line: &str| lines.iter().position(|&l| l == line).expect(line);
+
+    assert_eq!(lines[..5],
+               ["; ROM bank $00",
+                "00:0000  db $11,$11,$11,$11,$11,$11,$11,$11",
+                "L00_0008:",
+                "00:0008  C9        RET",
+                "00:0009  db $11,$11,$11,$11,$11,$11,$11,$11"]);
+    let i = at("00:0100  18 0E     JR $0110");
+    assert_eq!(lines[i + 1], "00:0102  db $11,$11,$11,$11,$11,$11,$11,$11");
+    let i = at("L00_0110:");
+    assert_eq!(lines[i + 1..i + 8],
+               ["00:0110  CD 00 02  CALL $0200",
+                "00:0113  CF        RST 08H",
+                "00:0114  20 01     JR NZ,$0117",
+                "00:0116  76        HALT",
+                "L00_0117:",
+                "00:0117  C3 00 40  JP Bank1",
+                "00:011A  db $11,$11,$11,$11,$11,$11,$11,$11"]);
+    let i = at("L00_0200:");
+    assert_eq!(lines[i + 1..i + 3],
+               ["00:0200  E9        JP HL", "00:0201  db $11,$11,$11,$11,$11,$11,$11,$11"]);
+
+    let i = at("; ROM bank $01");
+    assert_eq!(lines[i + 1..i + 5],
+               ["Bank1:",
+                "01:4000  3E 01     LD A,$01",
+                "01:4002  C9        RET",
+                "01:4003  db $11,$11,$11,$11,$11,$11,$11,$11"]);
+    assert_eq!(lines[lines.len() - 2..], ["01:7FFB  db $11,$11,$11,$18,$FE", ""]);
+}
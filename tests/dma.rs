@@ -0,0 +1,236 @@
+//! OAM DMA tests, after mooneye's `acceptance/oam_dma*` tests, and CGB VRAM DMA tests.
+//!
+//! PPU access restrictions are lifted, so that only the DMA ones apply.
+mod common;
+
+use common::System;
+use emulator::{device::device::Device, Builder};
+
+const DMA: u16 = 0xff46;
+const OAM: u16 = 0xfe00;
+const HRAM: u16 = 0xff80;
+const LCDC: u16 = 0xff40;
+const STAT: u16 = 0xff41;
+const DIV: u16 = 0xff04;
+const HDMA1: u16 = 0xff51;
+const HDMA5: u16 = 0xff55;
+
+impl System {
+    fn new() -> Self {
+        Self::with(Builder::default().gb_mode()).fill()
+    }
+
+    fn cgb() -> Self {
+        Self::with(Builder::default().gbc_mode()).fill()
+    }
+
+    // Fills 0xc000-0xc09f with `i ^ 0x5a`, and 0xd000-0xd09f with `i`.
+    fn fill(mut self) -> Self {
+        self.ppu().set_permissive(true);
+        for i in 0..0xa0 {
+            self.write(0xc000 + i, i as u8 ^ 0x5a);
+            self.write(0xd000 + i, i as u8);
+        }
+        self
+    }
+
+    // OAM contents, regardless of any transfer.
+    fn oam(&mut self, i: u16) -> u8 {
+        Device::read(self.mmu().ppu().oam(), OAM + i)
+    }
+
+    // VRAM contents (bank 0).
+    fn vram(&mut self, addr: u16) -> u8 {
+        Device::read(self.mmu().ppu().vram(), addr)
+    }
+
+    // Sets up a VRAM DMA transfer through HDMA1-HDMA4.
+    fn vram_dma(&mut self, src: u16, dst: u16) {
+        for (i, data) in [src >> 8, src, dst >> 8, dst].into_iter().enumerate() {
+            self.write(HDMA1 + i as u16, data as u8);
+        }
+    }
+}
+
+#[test]
+fn transfer_takes_160_cycles() {
+    let mut sys = System::new();
+    sys.write(DMA, 0xc0);
+    // One startup M-cycle, then one byte per M-cycle.
+    sys.idle(160);
+    assert_eq!(sys.oam(0x9e), 0x9e ^ 0x5a);
+    assert_eq!(sys.oam(0x9f), 0x00);
+    sys.idle(1);
+    assert!((0..0xa0).all(|i| sys.oam(i) == i as u8 ^ 0x5a));
+}
+
+#[test]
+fn oam_reads_ff_during_transfer() {
+    let mut sys = System::new();
+    sys.write(OAM, 0x42);
+    sys.write(DMA, 0xc0);
+    // Still readable during the startup M-cycle.
+    assert_eq!(sys.read(OAM), 0x42);
+    for _ in 0..160 {
+        assert_eq!(sys.read(OAM + 0x10), 0xff);
+    }
+    assert_eq!(sys.read(OAM), 0x5a);
+}
+
+#[test]
+fn oam_writes_ignored_during_transfer() {
+    let mut sys = System::new();
+    sys.write(DMA, 0xc0);
+    sys.idle(10);
+    sys.write(OAM + 0x9f, 0x42);
+    sys.idle(150);
+    assert_eq!(sys.oam(0x9f), 0x9f ^ 0x5a);
+    sys.write(OAM + 0x9f, 0x42);
+    assert_eq!(sys.oam(0x9f), 0x42);
+}
+
+#[test]
+fn hram_accessible_during_transfer() {
+    let mut sys = System::new();
+    sys.write(DMA, 0xc0);
+    sys.idle(10);
+    sys.write(HRAM, 0x42);
+    assert_eq!(sys.read(HRAM), 0x42);
+    assert_eq!(sys.read(DMA), 0xc0);
+}
+
+// Reads from the bus used by the transfer return the byte being copied.
+#[test]
+fn bus_conflicts() {
+    let mut sys = System::new();
+    sys.write(0x8000, 0x24);
+    sys.write(DMA, 0xd0);
+    assert_eq!(sys.read(0xc000), 0x5a);
+    for i in 0..0x10 {
+        assert_eq!(sys.read(0xc000), i);
+    }
+    // VRAM is on a different bus.
+    assert_eq!(sys.read(0x8000), 0x24);
+    // Writes to the external bus are lost.
+    sys.write(0xc0ff, 0x42);
+    sys.idle(160);
+    assert_eq!(sys.read(0xc0ff), 0x00);
+}
+
+#[test]
+fn register_reads_last_written_value() {
+    let mut sys = System::new();
+    assert_eq!(sys.read(DMA), 0x00);
+    sys.write(DMA, 0xc0);
+    assert_eq!(sys.read(DMA), 0xc0);
+    sys.idle(200);
+    assert_eq!(sys.read(DMA), 0xc0);
+}
+
+// oam_dma_sources: 0xe000-0xffff sources are mapped to work RAM.
+#[test]
+fn echo_sources() {
+    for (src, data) in [(0xe0, 0x5a), (0xf0, 0x00)] {
+        let mut sys = System::new();
+        sys.write(DMA, src);
+        sys.idle(161);
+        assert!((0..0xa0).all(|i| sys.oam(i) == i as u8 ^ data), "source {:02x}", src);
+        assert_eq!(sys.read(DMA), src);
+    }
+}
+
+// oam_dma_restart: the transfer starts over, and OAM stays inaccessible.
+#[test]
+fn restart() {
+    let mut sys = System::new();
+    sys.write(DMA, 0xc0);
+    sys.idle(50);
+    sys.write(DMA, 0xd0);
+    // The first transfer copies one more byte during the startup M-cycle.
+    assert_eq!(sys.read(OAM), 0xff);
+    assert_eq!(sys.oam(50), 50 ^ 0x5a);
+    for _ in 0..160 {
+        assert_eq!(sys.read(OAM), 0xff);
+    }
+    assert_eq!(sys.read(OAM), 0x00);
+    assert!((0..0xa0).all(|i| sys.oam(i) == i as u8));
+}
+
+#[test]
+fn general_purpose_vram_dma() {
+    let mut sys = System::cgb();
+    sys.vram_dma(0xc000, 0x8000);
+    assert_eq!(sys.read(HDMA5), 0xff);
+    sys.write(DIV, 0);
+    sys.write(HDMA5, 0x7f);
+    // The CPU is halted for 8 M-cycles per block, from the next M-cycle on.
+    assert_eq!(sys.read(DIV), ((2 + 128 * 8) * 4 >> 8) as u8);
+    assert_eq!(sys.read(HDMA5), 0xff);
+    assert!((0..0xa0).all(|i| sys.vram(0x8000 + i) == i as u8 ^ 0x5a));
+
+    // The source and destination registers advanced.
+    sys.write(0xc800, 0x42);
+    sys.write(HDMA5, 0x00);
+    assert_eq!(sys.vram(0x8800), 0x42);
+}
+
+#[test]
+fn hblank_vram_dma() {
+    let mut sys = System::cgb();
+    sys.vram_dma(0xd000, 0x9000);
+    sys.write(HDMA5, 0x82);
+    // One block per HBlank.
+    let mut hdma5 = sys.read(HDMA5);
+    let mut cycles = Vec::new();
+    let mut cycle = 0;
+    while cycle < 1000 {
+        let data = sys.read(HDMA5);
+        cycle += 1;
+        if data != hdma5 {
+            cycles.push((cycle, data));
+            hdma5 = data;
+            assert_eq!(sys.read(STAT) & 0x3, 0);
+            cycle += 1;
+        }
+    }
+    let [(a, 0x01), (b, 0x00), (c, 0xff)] = cycles[..] else {
+        panic!("HDMA5 changes: {:?}", cycles);
+    };
+    // A line is 114 M-cycles long, 8 of which the CPU is halted for.
+    assert_eq!((b - a, c - b), (114 - 8, 114 - 8));
+    assert!((0..0x30).all(|i| sys.vram(0x9000 + i) == i as u8));
+    assert_eq!(sys.vram(0x9030), 0x00);
+}
+
+#[test]
+fn hblank_vram_dma_cancel() {
+    let mut sys = System::cgb();
+    sys.vram_dma(0xd000, 0x9000);
+    sys.write(HDMA5, 0x85);
+    while sys.read(HDMA5) == 0x05 {}
+    sys.write(HDMA5, 0x00);
+    assert_eq!(sys.read(HDMA5), 0x84);
+    sys.idle(1000);
+    assert_eq!(sys.read(HDMA5), 0x84);
+    assert_eq!(sys.vram(0x900f), 0x0f);
+    assert_eq!(sys.vram(0x9010), 0x00);
+
+    // Restarting goes on from where the transfer stopped.
+    sys.write(HDMA5, 0x80);
+    sys.idle(200);
+    assert_eq!(sys.read(HDMA5), 0xff);
+    assert_eq!(sys.vram(0x901f), 0x1f);
+}
+
+// With the LCD off, a block is copied as soon as the transfer is started.
+#[test]
+fn hblank_vram_dma_lcd_off() {
+    let mut sys = System::cgb();
+    sys.write(LCDC, 0x00);
+    sys.vram_dma(0xd000, 0x9000);
+    sys.write(HDMA5, 0x81);
+    assert_eq!(sys.read(HDMA5), 0x00);
+    assert_eq!(sys.vram(0x900f), 0x0f);
+    sys.idle(1000);
+    assert_eq!(sys.read(HDMA5), 0x00);
+}
@@ -0,0 +1,149 @@
+//! I/O register decoding tests.
+mod common;
+
+use common::System;
+use emulator::Builder;
+
+const KEY1: u16 = 0xff4d;
+const VBK: u16 = 0xff4f;
+const HDMA1: u16 = 0xff51;
+const HDMA5: u16 = 0xff55;
+const BGPI: u16 = 0xff68;
+const BGPD: u16 = 0xff69;
+const OPRI: u16 = 0xff6c;
+const SVBK: u16 = 0xff70;
+
+// CGB-only registers.
+const CGB_IO: [u16; 13] = [KEY1, VBK, HDMA1, 0xff52, 0xff53, 0xff54, HDMA5, BGPI, BGPD, 0xff6a, 0xff6b, OPRI, SVBK];
+
+// The LCD is turned off, so that VRAM and palettes are always accessible.
+impl System {
+    fn new() -> Self {
+        Self::with(Builder::default().gb_mode()).lcd_off()
+    }
+
+    fn cgb() -> Self {
+        Self::with(Builder::default().gbc_mode()).lcd_off()
+    }
+
+    fn lcd_off(mut self) -> Self {
+        self.write(0xff40, 0x00);
+        self
+    }
+}
+
+#[test]
+fn cgb_registers_unmapped_in_gb_mode() {
+    let mut sys = System::new();
+    for addr in CGB_IO {
+        for data in [0x00, 0x01, 0x80, 0xff] {
+            sys.write(addr, data);
+            assert_eq!(sys.read(addr), 0xff, "{:04x}", addr);
+        }
+    }
+}
+
+#[test]
+fn no_banking_in_gb_mode() {
+    let mut sys = System::new();
+    sys.write(0x8000, 0x42);
+    sys.write(0xd000, 0x42);
+    sys.write(VBK, 0x01);
+    sys.write(SVBK, 0x02);
+    assert_eq!(sys.read(0x8000), 0x42);
+    assert_eq!(sys.read(0xd000), 0x42);
+
+    // No VRAM DMA either.
+    for (i, data) in [0xd0, 0x00, 0x80, 0x10].into_iter().enumerate() {
+        sys.write(HDMA1 + i as u16, data);
+    }
+    sys.write(HDMA5, 0x00);
+    assert_eq!(sys.read(0x8010), 0x00);
+}
+
+#[test]
+fn banking_in_cgb_mode() {
+    let mut sys = System::cgb();
+    sys.write(0x8000, 0x42);
+    sys.write(0xd000, 0x42);
+    sys.write(VBK, 0x01);
+    sys.write(SVBK, 0x02);
+    assert_eq!(sys.read(0x8000), 0x00);
+    assert_eq!(sys.read(0xd000), 0x00);
+    sys.write(VBK, 0x00);
+    sys.write(SVBK, 0x01);
+    assert_eq!(sys.read(0x8000), 0x42);
+    assert_eq!(sys.read(0xd000), 0x42);
+
+    sys.write(BGPI, 0x80);
+    sys.write(BGPD, 0x24);
+    sys.write(BGPI, 0x00);
+    assert_eq!(sys.read(BGPD), 0x24);
+}
+
+// After mooneye's `acceptance/bits` tests: bits with no function read as 1.
+#[test]
+fn unused_bits_read_as_one() {
+    let mut sys = System::new();
+    for (addr, mask) in [(0xff02, 0x7e),
+                         (0xff07, 0xf8),
+                         (0xff0f, 0xe0),
+                         (0xff10, 0x80),
+                         (0xff11, 0x3f),
+                         (0xff14, 0xbf),
+                         (0xff1a, 0x7f),
+                         (0xff1c, 0x9f),
+                         (0xff23, 0xbf)]
+    {
+        sys.write(addr, 0x00);
+        assert_eq!(sys.read(addr), mask, "{:04x}", addr);
+    }
+    sys.write(0xff41, 0x00);
+    assert_eq!(sys.read(0xff41) & 0x80, 0x80);
+    // Write-only registers.
+    for addr in [0xff13, 0xff18, 0xff1b, 0xff1d, 0xff20] {
+        sys.write(addr, 0x00);
+        assert_eq!(sys.read(addr), 0xff, "{:04x}", addr);
+    }
+    // SC clock speed, CGB only.
+    sys.write(0xff02, 0x02);
+    assert_eq!(sys.read(0xff02), 0x7e);
+
+    let mut sys = System::cgb();
+    for (addr, mask) in [(0xff02, 0x7c),
+                         (KEY1, 0x7e),
+                         (VBK, 0xfe),
+                         (HDMA1, 0xff),
+                         (BGPI, 0x40),
+                         (OPRI, 0xfe),
+                         (SVBK, 0xf8)]
+    {
+        sys.write(addr, 0x00);
+        assert_eq!(sys.read(addr), mask, "{:04x}", addr);
+    }
+    sys.write(0xff02, 0x02);
+    assert_eq!(sys.read(0xff02), 0x7e);
+}
+
+// After mooneye's `acceptance/unused_hwio` test.
+#[test]
+fn unmapped_io_reads_ff() {
+    let unmapped = [0xff03..=0xff03,
+                    0xff08..=0xff0e,
+                    0xff15..=0xff15,
+                    0xff1f..=0xff1f,
+                    0xff27..=0xff2f,
+                    0xff4c..=0xff4c,
+                    0xff4e..=0xff4e,
+                    0xff50..=0xff50,
+                    0xff56..=0xff67,
+                    0xff6d..=0xff6f,
+                    0xff71..=0xff7f,
+                    0xfea0..=0xfeff];
+    for mut sys in [System::new(), System::cgb()] {
+        for addr in unmapped.iter().cloned().flatten() {
+            sys.write(addr, 0x00);
+            assert_eq!(sys.read(addr), 0xff, "{:04x}", addr);
+        }
+    }
+}
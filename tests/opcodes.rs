@@ -0,0 +1,102 @@
+use emulator::{
+    cartridge::cartridge::Cartridge,
+    cpu::cpu::opcode::{Cond, Effect, Mnemonic, CB_OPCODES, OPCODES},
+    device::device::Device,
+    Builder,
+};
+
+// 64KiB of flat, writable memory. Only the cartridge ranges are ever mapped to it.
+struct Flat(Vec<u8>);
+
+impl Device for Flat {
+    fn read(&self, addr: u16) -> u8 {
+        self.0[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.0[addr as usize] = data
+    }
+}
+
+impl Cartridge for Flat {}
+
+// Executes `code` at 0x0100 with the given flags. Returns the elapsed cycles, the new PC and F.
+fn run(code: &[u8], f: u8) -> (u64, u16, u8) {
+    let mut rom = vec![0; 0x10000];
+    rom[0x100..0x100 + code.len()].copy_from_slice(code);
+    let mut gb = Builder::default().cartridge(Flat(rom)).skip_boot().build();
+    let reg = gb.cpu_mut().reg_mut();
+    reg.set_af(u16::from(f));
+    reg.set_bc(0xc000);
+    reg.set_de(0xc000);
+    reg.set_hl(0xc000);
+    reg.sp = 0xd000;
+    let cycles = gb.step();
+    let reg = gb.cpu().reg();
+    (cycles, reg.pc, reg.af() as u8)
+}
+
+fn taken(cond: Cond, f: u8) -> bool {
+    match cond {
+        Cond::NZ => f & 0x80 == 0,
+        Cond::Z => f & 0x80 != 0,
+        Cond::NC => f & 0x10 == 0,
+        Cond::C => f & 0x10 != 0,
+    }
+}
+
+fn check_flag(name: &str, effect: Effect, bit: u8, before: u8, after: u8) {
+    match effect {
+        Effect::Unaffected => assert_eq!(after & bit, before & bit, "{name} modified"),
+        Effect::Reset => assert_eq!(after & bit, 0, "{name} not reset"),
+        Effect::Set => assert_eq!(after & bit, bit, "{name} not set"),
+        Effect::Affected => {}
+    }
+}
+
+#[test]
+fn length_timing_and_flags_match_table() {
+    for prefixed in [false, true] {
+        for opcode in 0..=0xffu8 {
+            if !prefixed && opcode == 0xcb {
+                continue;
+            }
+            let (instr, code) = if prefixed {
+                (&CB_OPCODES[opcode as usize], vec![0xcb, opcode, 0, 0])
+            } else {
+                (&OPCODES[opcode as usize], vec![opcode, 0, 0])
+            };
+            for f in [0x00, 0xf0] {
+                let (cycles, pc, flags) = run(&code, f);
+                let branch = match instr.cond() {
+                    Some(cond) => taken(cond, f),
+                    None => instr.branch_cycles.is_some(),
+                };
+                let expected = if branch {
+                    instr.branch_cycles.unwrap()
+                } else {
+                    instr.cycles
+                };
+                assert_eq!(cycles, 4 * u64::from(expected), "{instr} (F={f:02x}) timing");
+
+                // Unconditional jumps, calls and returns always branch.
+                let jump = match instr.mnemonic {
+                    Mnemonic::Jr | Mnemonic::Jp | Mnemonic::Call | Mnemonic::Ret => {
+                        instr.cond().is_none()
+                    }
+                    Mnemonic::Reti | Mnemonic::Rst | Mnemonic::Illegal => true,
+                    _ => false,
+                };
+                if !branch && !jump {
+                    assert_eq!(pc, 0x100 + u16::from(instr.len), "{instr} (F={f:02x}) length");
+                }
+                if instr.mnemonic != Mnemonic::Pop {
+                    check_flag("Z", instr.flags.z, 0x80, f, flags);
+                    check_flag("N", instr.flags.n, 0x40, f, flags);
+                    check_flag("H", instr.flags.h, 0x20, f, flags);
+                    check_flag("C", instr.flags.c, 0x10, f, flags);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,501 @@
+//! PPU timing and rendering tests.
+//!
+//! The PPU is stepped one dot at a time, and registers are accessed without ticking the MMU.
+//! Memory access restrictions are lifted unless a test is about them.
+mod common;
+
+use common::System;
+use emulator::{
+    ppu::ppu::{palette::Color, Framebuffer, LCD_WIDTH},
+    Builder,
+};
+
+const LCDC: u16 = 0xff40;
+const STAT: u16 = 0xff41;
+const SCX: u16 = 0xff43;
+const LY: u16 = 0xff44;
+const LYC: u16 = 0xff45;
+const BGP: u16 = 0xff47;
+const OBP0: u16 = 0xff48;
+const OBP1: u16 = 0xff49;
+const WY: u16 = 0xff4a;
+const WX: u16 = 0xff4b;
+const OBPI: u16 = 0xff6a;
+const OBPD: u16 = 0xff6b;
+const OPRI: u16 = 0xff6c;
+const IF: u16 = 0xff0f;
+const STAT_INT: u8 = 0x2;
+
+const FRAME: u64 = 70_224;
+
+const BLACK: Color = [0x00, 0x00, 0x00];
+const WHITE: Color = [0xff, 0xff, 0xff];
+const GRAY: Color = [0xaa, 0xaa, 0xaa];
+const DARK: Color = [0x55, 0x55, 0x55];
+const RED: Color = [0xff, 0x00, 0x00];
+const GREEN: Color = [0x00, 0xff, 0x00];
+
+impl System<Framebuffer> {
+    // LCD on with BG at 0x9800 and tile data at 0x8000. Tile 0 is solid color 3.
+    fn new() -> Self {
+        Self::with(Builder::default().video(Framebuffer::default()).gb_mode()).setup()
+    }
+
+    fn cgb() -> Self {
+        Self::with(Builder::default().video(Framebuffer::default()).gbc_mode()).setup()
+    }
+
+    fn setup(mut self) -> Self {
+        self.ppu().set_permissive(true);
+        for addr in 0x8000..0x8010 {
+            self.poke(addr, 0xff);
+        }
+        self.poke(BGP, 0xe4);
+        self
+    }
+
+    fn mode(&mut self) -> u8 {
+        self.peek(STAT) & 0x3
+    }
+
+    // Window map at 0x9c00: window lines 0-7 use tile 1 (color 1), lines 8-15 use tile 2
+    // (color 2) and the rest use tile 0.
+    fn window(&mut self) {
+        for addr in 0x8010..0x8020 {
+            self.poke(addr, if addr & 1 == 0 { 0xff } else { 0x00 });
+        }
+        for addr in 0x8020..0x8030 {
+            self.poke(addr, if addr & 1 == 0 { 0x00 } else { 0xff });
+        }
+        for x in 0..32 {
+            self.poke(0x9c00 + x, 1);
+            self.poke(0x9c20 + x, 2);
+        }
+        self.poke(LCDC, 0xf1);
+    }
+
+    // Places a sprite using tile 0 on lines 0 to 7.
+    fn sprite(&mut self, oam: u16, x: u8, flags: u8) {
+        self.poke(0xfe00 + 4 * oam, 16);
+        self.poke(0xfe01 + 4 * oam, x);
+        self.poke(0xfe02 + 4 * oam, 0);
+        self.poke(0xfe03 + 4 * oam, flags);
+    }
+
+    fn dot(&mut self) {
+        self.ppu().step(1);
+    }
+
+    // Runs until the beginning of mode 3 on line `ly`.
+    fn run_to_pixels(&mut self, ly: u8) {
+        while self.peek(LY) != ly || self.mode() != 2 {
+            self.dot();
+        }
+        while self.mode() != 3 {
+            self.dot();
+        }
+    }
+
+    // Length of mode 3 on line `ly`, in dots.
+    fn pixels(&mut self, ly: u8) -> u64 {
+        self.run_to_pixels(ly);
+        let mut dots = 0;
+        while self.mode() == 3 {
+            self.dot();
+            dots += 1;
+        }
+        dots
+    }
+
+    // Ticks the MMU for `dots` dots, the way the CPU does, and returns the number of STAT
+    // interrupts requested meanwhile.
+    fn stat_interrupts(&mut self, dots: u64) -> usize {
+        let mut n = 0;
+        for _ in 0..dots / 4 {
+            self.idle(1);
+            if self.peek(IF) & STAT_INT != 0 {
+                self.poke(IF, 0);
+                n += 1;
+            }
+        }
+        n
+    }
+
+    // Runs until the current frame is drawn.
+    fn finish_frame(&mut self) -> Vec<Color> {
+        while self.peek(LY) != 144 {
+            self.dot();
+        }
+        self.ppu().video().pixels().to_vec()
+    }
+}
+
+#[test]
+fn line_is_456_dots() {
+    let mut sys = System::new();
+    sys.run_to_pixels(10);
+    let mut dots = 0;
+    while sys.peek(LY) != 11 || sys.mode() != 3 {
+        sys.dot();
+        dots += 1;
+    }
+    assert_eq!(dots, 456);
+}
+
+#[test]
+fn mode3_is_172_dots() {
+    let mut sys = System::new();
+    assert_eq!(sys.pixels(10), 172);
+}
+
+#[test]
+fn mode3_scx_fine_scroll() {
+    for scx in 0..16 {
+        let mut sys = System::new();
+        sys.poke(SCX, scx);
+        assert_eq!(sys.pixels(10), 172 + u64::from(scx & 0x7), "SCX={}", scx);
+    }
+}
+
+#[test]
+fn mode3_window() {
+    let mut sys = System::new();
+    sys.poke(LCDC, 0xb1);
+    sys.poke(WY, 0);
+    sys.poke(WX, 87);
+    assert_eq!(sys.pixels(10), 172 + 6);
+
+    // not visible on this line
+    let mut sys = System::new();
+    sys.poke(LCDC, 0xb1);
+    sys.poke(WY, 20);
+    sys.poke(WX, 87);
+    assert_eq!(sys.pixels(10), 172);
+}
+
+#[test]
+fn mode3_sprites() {
+    // (X, penalty) with SCX=0
+    for (x, penalty) in [(8, 11), (9, 10), (12, 7), (13, 6), (16, 11), (167, 6)] {
+        let mut sys = System::new();
+        sys.poke(LCDC, 0x93);
+        sys.poke(0xfe00, 16);
+        sys.poke(0xfe01, x);
+        assert_eq!(sys.pixels(0), 172 + penalty, "X={}", x);
+        // not on this line
+        assert_eq!(sys.pixels(10), 172, "X={}", x);
+    }
+
+    // sprites at the same X only wait for the BG fetcher once
+    let mut sys = System::new();
+    sys.poke(LCDC, 0x93);
+    for entry in 0..2 {
+        sys.poke(0xfe00 + 4 * entry, 16);
+        sys.poke(0xfe01 + 4 * entry, 8);
+    }
+    assert_eq!(sys.pixels(0), 172 + 11 + 6);
+}
+
+#[test]
+fn palette_write_during_mode3() {
+    let mut sys = System::new();
+    sys.run_to_pixels(50);
+    for _ in 0..100 {
+        sys.dot();
+    }
+    sys.poke(BGP, 0x00);
+    let frame = sys.finish_frame();
+    let line = &frame[50 * LCD_WIDTH..51 * LCD_WIDTH];
+    assert_eq!(line[0], BLACK);
+    assert_eq!(line[LCD_WIDTH - 1], WHITE);
+    // the pixel output when BGP was written
+    let x = line.iter().position(|&c| c == WHITE).unwrap();
+    assert!((80..100).contains(&x), "x={}", x);
+    // other lines
+    assert!(frame[..50 * LCD_WIDTH].iter().all(|&c| c == BLACK));
+    assert!(frame[51 * LCD_WIDTH..].iter().all(|&c| c == WHITE));
+}
+
+#[test]
+fn ten_sprites_per_line() {
+    let mut sys = System::new();
+    sys.poke(LCDC, 0x93);
+    sys.poke(OBP0, 0x00);
+    // 6 dots each
+    for oam in 0..11 {
+        sys.sprite(oam, 13 + 8 * oam as u8, 0);
+    }
+    assert_eq!(sys.pixels(0), 172 + 10 * 6);
+    let frame = sys.finish_frame();
+    for oam in 0..11 {
+        let expected = if oam < 10 { WHITE } else { BLACK };
+        assert_eq!(frame[5 + 8 * oam], expected, "OAM {}", oam);
+    }
+}
+
+#[test]
+fn sprite_priority_dmg() {
+    let mut sys = System::new();
+    sys.poke(LCDC, 0x93);
+    sys.poke(OBP0, 0x00);
+    sys.poke(OBP1, 0x40);
+    // lowest X wins
+    sys.sprite(0, 20, 0x10);
+    sys.sprite(1, 16, 0x00);
+    // same X: lowest OAM index wins
+    sys.sprite(2, 40, 0x10);
+    sys.sprite(3, 40, 0x00);
+    // lowest X wins at the left edge too, where both are fetched at the first pixel
+    sys.sprite(4, 6, 0x10);
+    sys.sprite(5, 2, 0x00);
+    sys.run_to_pixels(0);
+    let frame = sys.finish_frame();
+    assert_eq!(frame[0..2], [WHITE; 2]);
+    assert_eq!(frame[2..6], [GRAY; 4]);
+    assert_eq!(frame[8..16], [WHITE; 8]);
+    assert_eq!(frame[16..20], [GRAY; 4]);
+    assert_eq!(frame[32..40], [GRAY; 8]);
+}
+
+#[test]
+fn sprite_priority_cgb() {
+    for (opri, overlap) in [(0x00, GREEN), (0x01, RED)] {
+        let mut sys = System::cgb();
+        sys.poke(LCDC, 0x93);
+        for (palette, color) in [(0, 0x001f_u16), (1, 0x03e0)] {
+            sys.poke(OBPI, 0x80 | (8 * palette + 6));
+            sys.poke(OBPD, color as u8);
+            sys.poke(OBPD, (color >> 8) as u8);
+        }
+        sys.poke(OPRI, opri);
+        assert_eq!(sys.peek(OPRI), 0xfe | opri);
+        sys.sprite(0, 20, 0x01);
+        sys.sprite(1, 16, 0x00);
+        sys.run_to_pixels(0);
+        let frame = sys.finish_frame();
+        assert_eq!(frame[8..12], [RED; 4], "OPRI={}", opri);
+        assert_eq!(frame[12..16], [overlap; 4], "OPRI={}", opri);
+        assert_eq!(frame[16..20], [GREEN; 4], "OPRI={}", opri);
+    }
+}
+
+#[test]
+fn window_line_counter() {
+    let mut sys = System::new();
+    sys.window();
+    sys.poke(WY, 0);
+    sys.poke(WX, 7);
+    // window hidden on lines 4-19
+    sys.run_to_pixels(4);
+    sys.poke(LCDC, 0xd1);
+    sys.run_to_pixels(20);
+    sys.poke(LCDC, 0xf1);
+    let frame = sys.finish_frame();
+    let line = |ly: usize| frame[ly * LCD_WIDTH];
+    assert_eq!(line(3), GRAY);
+    assert_eq!(line(4), BLACK);
+    assert_eq!(line(19), BLACK);
+    // window lines 4 and 8
+    assert_eq!(line(20), GRAY);
+    assert_eq!(line(23), GRAY);
+    assert_eq!(line(24), DARK);
+    assert_eq!(line(31), DARK);
+    assert_eq!(line(32), BLACK);
+}
+
+#[test]
+fn window_wy_latched_per_frame() {
+    let mut sys = System::new();
+    sys.window();
+    sys.poke(WX, 7);
+    sys.poke(WY, 10);
+    sys.run_to_pixels(20);
+    sys.poke(WY, 100);
+    let frame = sys.finish_frame();
+    let line = |ly: usize| frame[ly * LCD_WIDTH];
+    assert_eq!(line(9), BLACK);
+    assert_eq!(line(10), GRAY);
+    assert_eq!(line(21), DARK);
+
+    // WY matches a line that has already been drawn
+    sys.run_to_pixels(50);
+    sys.poke(WY, 30);
+    let frame = sys.finish_frame();
+    assert!(frame.iter().all(|&c| c == BLACK));
+}
+
+#[test]
+fn window_wx() {
+    // (WX, SCX, first pixels of line 0), window pixels 8-15 are dark
+    for (wx, scx, pixels) in [(7, 0, [GRAY; 8]),
+                              (8, 0, [BLACK, GRAY, GRAY, GRAY, GRAY, GRAY, GRAY, GRAY]),
+                              (3, 0, [GRAY, GRAY, GRAY, GRAY, DARK, DARK, DARK, DARK]),
+                              (0, 0, [GRAY, DARK, DARK, DARK, DARK, DARK, DARK, DARK]),
+                              (0, 2, [DARK, DARK, DARK, DARK, DARK, DARK, DARK, GRAY])]
+    {
+        let mut sys = System::new();
+        sys.window();
+        sys.poke(0x9c01, 2);
+        sys.poke(WY, 0);
+        sys.poke(WX, wx);
+        sys.poke(SCX, scx);
+        let frame = sys.finish_frame();
+        assert_eq!(frame[..8], pixels, "WX={} SCX={}", wx, scx);
+    }
+
+    // WX=166: the window covers the last pixel and the whole next line
+    let mut sys = System::new();
+    sys.window();
+    sys.poke(WY, 0);
+    sys.poke(WX, 166);
+    let frame = sys.finish_frame();
+    assert!(frame[..LCD_WIDTH - 1].iter().all(|&c| c == BLACK));
+    assert_eq!(frame[LCD_WIDTH - 1], GRAY);
+    assert!(frame[LCD_WIDTH..2 * LCD_WIDTH].iter().all(|&c| c == GRAY));
+}
+
+#[test]
+fn stat_interrupt_blocking() {
+    // (STAT, interrupts per frame) with LYC=50
+    for (stat, n) in [(0x08, 144),
+                      (0x10, 1),
+                      // mode 2, and line 144
+                      (0x20, 145),
+                      (0x40, 1),
+                      // mode 2 blocked by mode 0 of the previous line, except on line 0
+                      (0x28, 145),
+                      // mode 1 blocked by mode 0 of line 143
+                      (0x18, 144),
+                      // mode 2 of line 0 blocked by mode 1, line 144 merged with mode 1
+                      (0x30, 144),
+                      // mode 0 of line 50 blocked by LY=LYC
+                      (0x48, 144)]
+    {
+        let mut sys = System::new();
+        sys.poke(LYC, 50);
+        sys.poke(STAT, stat);
+        sys.stat_interrupts(4);
+        assert_eq!(sys.stat_interrupts(FRAME), n, "STAT={:02x}", stat);
+        assert_eq!(sys.stat_interrupts(FRAME), n, "STAT={:02x}", stat);
+    }
+}
+
+#[test]
+fn stat_write_dmg_bug() {
+    for (cgb, n) in [(false, 1), (true, 0)] {
+        let mut sys = if cgb { System::cgb() } else { System::new() };
+        sys.poke(LYC, 200);
+        // mode 1
+        while sys.peek(LY) != 145 {
+            sys.dot();
+        }
+        sys.stat_interrupts(4);
+        sys.poke(STAT, 0x00);
+        assert_eq!(sys.stat_interrupts(4), n, "CGB={}", cgb);
+
+        // no source is active in mode 3
+        sys.run_to_pixels(10);
+        sys.stat_interrupts(4);
+        sys.poke(STAT, 0x00);
+        assert_eq!(sys.stat_interrupts(4), 0, "CGB={}", cgb);
+
+        // nor in mode 2, whose source isn't enabled by the bug
+        while sys.peek(LY) != 20 || sys.mode() != 2 {
+            sys.dot();
+        }
+        sys.stat_interrupts(4);
+        sys.poke(STAT, 0x00);
+        assert_eq!(sys.stat_interrupts(4), 0, "CGB={}", cgb);
+    }
+}
+
+#[test]
+fn ly_lyc_line_153() {
+    // (LYC, dots of line 153 where LY=LYC is set)
+    for (lyc, matches) in [(153, 4..8), (0, 12..16)] {
+        let mut sys = System::new();
+        sys.poke(LYC, lyc);
+        while sys.peek(LY) != 153 {
+            sys.dot();
+        }
+        for dot in 0..16 {
+            let ly = if dot < 4 { 153 } else { 0 };
+            assert_eq!(sys.peek(LY), ly, "LYC={} dot {}", lyc, dot);
+            assert_eq!(sys.peek(STAT) & 0x4 != 0, matches.contains(&dot), "LYC={} dot {}", lyc, dot);
+            sys.dot();
+        }
+    }
+}
+
+#[test]
+fn access_restrictions() {
+    let mut sys = System::cgb();
+    sys.poke(0x8010, 0x12);
+    sys.poke(0xfe00, 0x34);
+    sys.poke(0xff68, 0x80);
+    sys.poke(0xff69, 0x56);
+    sys.poke(0xff68, 0x80);
+    sys.ppu().set_permissive(false);
+
+    // (mode, VRAM and palette data accessible, OAM accessible)
+    for (mode, vram, oam) in [(2, true, false), (3, false, false), (0, true, true), (1, true, true)] {
+        while sys.mode() != mode {
+            sys.dot();
+        }
+        let expected = |ok: bool, data: u8| if ok { data } else { 0xff };
+        assert_eq!(sys.peek(0x8010), expected(vram, 0x12), "mode {}", mode);
+        assert_eq!(sys.peek(0xff69), expected(vram, 0x56), "mode {}", mode);
+        assert_eq!(sys.peek(0xfe00), expected(oam, 0x34), "mode {}", mode);
+        // writes are dropped
+        sys.poke(0x8010, 0x00);
+        sys.poke(0xfe00, 0x00);
+        sys.ppu().set_permissive(true);
+        assert_eq!(sys.peek(0x8010), if vram { 0x00 } else { 0x12 }, "mode {}", mode);
+        assert_eq!(sys.peek(0xfe00), if oam { 0x00 } else { 0x34 }, "mode {}", mode);
+        sys.poke(0x8010, 0x12);
+        sys.poke(0xfe00, 0x34);
+        sys.ppu().set_permissive(false);
+    }
+
+    // palette data writes during mode 3 are dropped, but the index is still incremented
+    sys.run_to_pixels(10);
+    sys.poke(0xff69, 0x00);
+    assert_eq!(sys.peek(0xff68), 0xc1);
+    sys.ppu().set_permissive(true);
+    assert_eq!(sys.ppu().color_pal().bgp[0], 0x56);
+}
+
+// Sprites already behind the current pixel when objects are enabled are skipped.
+#[test]
+fn obj_enable_during_mode3() {
+    let mut sys = System::new();
+    sys.poke(LCDC, 0x91);
+    sys.poke(OBP0, 0x00);
+    sys.sprite(0, 8, 0);
+    sys.sprite(1, 20, 0);
+    sys.sprite(2, 100, 0);
+    sys.run_to_pixels(2);
+    for _ in 0..80 {
+        sys.dot();
+    }
+    sys.poke(LCDC, 0x93);
+    let frame = sys.finish_frame();
+    let line = &frame[2 * LCD_WIDTH..3 * LCD_WIDTH];
+    assert_eq!(line[..92], [BLACK; 92]);
+    assert_eq!(line[92..100], [WHITE; 8]);
+}
+
+// Switching from 8x16 to 8x8 objects after the OAM scan keeps the low bits of the row.
+#[test]
+fn obj_size_switch_during_line() {
+    let mut sys = System::new();
+    sys.poke(LCDC, 0x97);
+    sys.poke(OBP0, 0x00);
+    // Y-flipped, so that row 10 of the 8x16 sprite becomes row 5 of tile 0.
+    sys.sprite(0, 50, 0x40);
+    sys.run_to_pixels(10);
+    sys.poke(LCDC, 0x93);
+    let frame = sys.finish_frame();
+    let line = &frame[10 * LCD_WIDTH..11 * LCD_WIDTH];
+    assert_eq!(line[42..50], [WHITE; 8]);
+}
@@ -0,0 +1,38 @@
+//! Execution profiler tests.
+use emulator::{cartridge, cpu::cpu::profile::Profiler, Builder};
+
+#[test]
+fn cycles_and_calls() {
+    let mut rom = vec![0; 0x8000];
+    #[rustfmt::skip]
+    let code = [
+        0xcd, 0x00, 0x02, // $0100: CALL $0200
+        0xcd, 0x00, 0x02, // $0103: CALL $0200
+        0x18, 0xfe,       // $0106: JR $0106
+    ];
+    rom[0x100..0x100 + code.len()].copy_from_slice(&code);
+    // $0200: NOP, RET
+    rom[0x200..0x202].copy_from_slice(&[0x00, 0xc9]);
+
+    let cartridge = cartridge::from_bytes(&rom).unwrap();
+    let mut gb = Builder::default().cartridge(cartridge).gb_mode().skip_boot().build();
+    gb.cpu_mut().set_profiler(Some(Profiler::new()));
+    for _ in 0..6 + 10 {
+        gb.step();
+    }
+
+    let profiler = gb.cpu().profiler().unwrap();
+    assert_eq!(profiler.total(), 2 * (24 + 4 + 16) + 10 * 12);
+    assert_eq!(profiler.banks().into_iter().collect::<Vec<_>>(), [(0, profiler.total())]);
+    // Hottest first, ties by address.
+    assert_eq!(profiler.hot_spots(5),
+               [((0, 0x0106), 120), ((0, 0x0201), 32), ((0, 0x0100), 24), ((0, 0x0103), 24), ((0, 0x0200), 8)]);
+    assert_eq!(profiler.hot_spots(2).len(), 2);
+    assert_eq!(profiler.calls(5), [((0, 0x0200), 2)]);
+
+    let mut report = Vec::new();
+    profiler.report(&mut report, 1, None).unwrap();
+    let report = String::from_utf8(report).unwrap();
+    assert!(report.starts_with("total: 208 cycles\n"), "{}", report);
+    assert!(report.contains("\nhot spots:\n           120   57.7%  00:0106\n\ncalls:\n"), "{}", report);
+}
@@ -0,0 +1,243 @@
+//! Runs a directory of test ROMs headless and reports the results in a summary table.
+//!
+//! The ROMs are not distributed with the crate. Point `GB_TEST_ROMS` at a directory of `.gb`
+//! and `.gbc` files (by default `tests/roms` is used, searched recursively), then run
+//! `cargo test --release --test roms -- --nocapture`. The test passes trivially when the
+//! directory doesn't exist.
+//!
+//! Pass/fail is decided by the conventions of the common test suites:
+//!
+//! - A ROM with a reference screenshot next to it (`dmg-acid2.gb` and `dmg-acid2.png`) passes
+//!   if the screen matches it once the ROM executes `LD B,B`, or when it times out.
+//! - A ROM with `mooneye` or `mts` in its path ends the test by executing `LD B,B`, and passes
+//!   if B, C, D, E, H and L hold the Fibonacci sequence 3, 5, 8, 13, 21, 34.
+//! - Any other ROM passes or fails when it prints `Passed` or `Failed` on the serial port
+//!   (blargg). `LD B,B` has no special meaning for those.
+//!
+//! ROMs run for at most `GB_TEST_ROMS_TIMEOUT` emulated seconds (60 by default).
+use emulator::{
+    cartridge,
+    cpu::cpu::Cpu,
+    device::device::Device,
+    ppu::ppu::{palette::Color, Framebuffer, LCD_HEIGHT, LCD_WIDTH},
+    Builder, CLOCK, FRAME_CYCLES,
+};
+use std::{
+    env,
+    fmt::Write,
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+// LD B,B, used by mooneye and the acid2 tests as a software breakpoint.
+const LD_B_B: u8 = 0x40;
+const FIBONACCI: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Outcome {
+    Pass,
+    Fail(String),
+    Timeout,
+    Skip(String),
+}
+
+fn run(path: &Path, mooneye: bool, frames: u64) -> Outcome {
+    let rom = match fs::read(path) {
+        Ok(rom) => rom,
+        Err(e) => return Outcome::Skip(e.to_string()),
+    };
+    let Ok(cartridge) = cartridge::from_bytes(&rom) else {
+        return Outcome::Skip("unsupported cartridge type".to_string());
+    };
+    let builder = Builder::default().cartridge(cartridge)
+                                    .video(Framebuffer::default())
+                                    .skip_boot();
+    // CGB flag of the header.
+    let mut gb = if rom.get(0x143).is_some_and(|f| f & 0x80 != 0) {
+        builder.gbc_mode().build()
+    } else {
+        builder.gb_mode().build()
+    };
+    let reference = path.with_extension("png");
+    let reference = reference.exists().then_some(reference);
+
+    let mut breakpoint = false;
+    for _ in 0..frames {
+        breakpoint = gb.emulate_frame_until(|gb| {
+            let pc = gb.cpu().reg().pc;
+            (mooneye || reference.is_some()) && !gb.cpu().halt() && gb.mmu().read(pc) == LD_B_B
+        });
+        if breakpoint {
+            break;
+        }
+        if reference.is_none() && !mooneye {
+            let output = String::from_utf8_lossy(gb.mmu().serial().output());
+            if output.contains("Passed") {
+                return Outcome::Pass;
+            }
+            if output.contains("Failed") {
+                return Outcome::Fail(output.trim().lines().last().unwrap_or_default().to_string());
+            }
+        }
+    }
+
+    if let Some(reference) = reference {
+        // Finish the current frame, then draw a whole one.
+        gb.emulate_frame();
+        gb.emulate_frame();
+        return compare(gb.mmu().ppu().video().pixels(), &reference);
+    }
+    if breakpoint {
+        return fibonacci(gb.cpu());
+    }
+    Outcome::Timeout
+}
+
+// Whether the ROM belongs to mooneye's test suite, from its path relative to the ROM directory.
+fn is_mooneye(path: &Path) -> bool {
+    path.components().any(|c| {
+        let name = c.as_os_str().to_string_lossy().to_ascii_lowercase();
+        name.contains("mooneye") || name.starts_with("mts")
+    })
+}
+
+fn fibonacci(cpu: &Cpu) -> Outcome {
+    let reg = cpu.reg();
+    let regs = [reg.b, reg.c, reg.d, reg.e, reg.h, reg.l];
+    if regs == FIBONACCI {
+        Outcome::Pass
+    } else {
+        Outcome::Fail(format!("registers {:02X?}", regs))
+    }
+}
+
+fn compare(screen: &[Color], reference: &Path) -> Outcome {
+    let expected = match load_png(reference) {
+        Ok(expected) => expected,
+        Err(e) => return Outcome::Skip(format!("{}: {}", reference.display(), e)),
+    };
+    if screen.len() != expected.len() {
+        return Outcome::Fail("no frame drawn".to_string());
+    }
+    match screen.iter().zip(&expected).filter(|(a, b)| a != b).count() {
+        0 => Outcome::Pass,
+        n => Outcome::Fail(format!("{} pixels differ from the reference", n)),
+    }
+}
+
+// Loads a 160x144 PNG as RGB.
+fn load_png(path: &Path) -> Result<Vec<Color>, String> {
+    let file = fs::File::open(path).map_err(|e| e.to_string())?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(|e| e.to_string())?;
+    if (info.width, info.height) != (LCD_WIDTH as u32, LCD_HEIGHT as u32) {
+        return Err(format!("expected {}x{} pixels", LCD_WIDTH, LCD_HEIGHT));
+    }
+    let samples = info.color_type.samples();
+    Ok(buf[..info.buffer_size()].chunks(samples)
+                                .map(|p| match samples {
+                                    1 | 2 => [p[0]; 3],
+                                    _ => [p[0], p[1], p[2]],
+                                })
+                                .collect())
+}
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        if path.is_dir() {
+            find_roms(&path, roms);
+        } else if path.extension().is_some_and(|e| e == "gb" || e == "gbc") {
+            roms.push(path);
+        }
+    }
+}
+
+#[test]
+fn test_roms() {
+    let dir = env::var_os("GB_TEST_ROMS").map_or_else(|| PathBuf::from("tests/roms"), PathBuf::from);
+    let frames = env::var("GB_TEST_ROMS_TIMEOUT").ok()
+                                                 .and_then(|s| s.parse().ok())
+                                                 .unwrap_or(60)
+                  * CLOCK
+                  / FRAME_CYCLES;
+    if !dir.is_dir() {
+        eprintln!("skipped: {} not found (set GB_TEST_ROMS)", dir.display());
+        return;
+    }
+    let mut roms = Vec::new();
+    find_roms(&dir, &mut roms);
+    roms.sort();
+
+    // Run the ROMs in parallel. Panics are reported as failures.
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, Outcome)> = thread::scope(|s| {
+        let workers: Vec<_> = (0..thread::available_parallelism().map_or(1, |n| n.get()))
+            .map(|_| {
+                s.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(path) = roms.get(i) else {
+                            break;
+                        };
+                        let mooneye = is_mooneye(path.strip_prefix(&dir).unwrap_or(path));
+                        let run = || run(path, mooneye, frames);
+                        let outcome = panic::catch_unwind(AssertUnwindSafe(run))
+                            .unwrap_or_else(|e| {
+                                let msg = e.downcast_ref::<String>()
+                                           .map(String::as_str)
+                                           .or_else(|| e.downcast_ref::<&str>().copied())
+                                           .unwrap_or("panicked");
+                                Outcome::Fail(format!("panic: {}", msg))
+                            });
+                        results.push((i, outcome));
+                    }
+                    results
+                })
+            })
+            .collect();
+        workers.into_iter().flat_map(|w| w.join().unwrap()).collect()
+    });
+    results.sort_by_key(|(i, _)| *i);
+
+    let width = roms.iter()
+                    .map(|p| p.strip_prefix(&dir).unwrap_or(p).display().to_string().len())
+                    .max()
+                    .unwrap_or(0);
+    let mut table = String::new();
+    let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+    for (i, outcome) in &results {
+        let name = roms[*i].strip_prefix(&dir).unwrap_or(&roms[*i]).display().to_string();
+        let (result, detail) = match outcome {
+            Outcome::Pass => {
+                passed += 1;
+                ("pass", "")
+            }
+            Outcome::Fail(detail) => {
+                failed += 1;
+                ("FAIL", detail.as_str())
+            }
+            Outcome::Timeout => {
+                failed += 1;
+                ("FAIL", "timed out")
+            }
+            Outcome::Skip(detail) => {
+                skipped += 1;
+                ("skip", detail.as_str())
+            }
+        };
+        writeln!(table, "{:<width$}  {}  {}", name, result, detail, width = width).unwrap();
+    }
+    println!("{}", table);
+    println!("{} passed, {} failed, {} skipped", passed, failed, skipped);
+    assert_eq!(failed, 0);
+}
@@ -0,0 +1,196 @@
+//! Runs the [SingleStepTests](https://github.com/SingleStepTests/sm83) JSON test vectors.
+//!
+//! The vectors are not distributed with the crate. Point `SM83_TESTS` at the `v1` directory of
+//! a checkout (by default `tests/sm83/v1` is used), then run `cargo test --test sm83`. The test
+//! passes trivially when the directory doesn't exist.
+//!
+//! Every test executes a single instruction from a given initial state, and checks the final
+//! registers and memory, and the address, data and direction of the access made on every
+//! M-cycle.
+//!
+//! The vectors model the overlap of the SM83 fetch and execute stages: the opcode has already
+//! been fetched (PC points past it), and the last M-cycle fetches the next opcode. The CPU
+//! fetches the opcode as part of the instruction, so it starts one byte earlier, and the
+//! fetch of the next opcode is done by hand.
+use emulator::cpu::cpu::{bus::Bus, Cpu};
+use serde_json::Value;
+use std::{env, fs, path::PathBuf};
+
+// Maximum number of failures reported per file.
+const MAX_REPORTED: usize = 5;
+
+// Kind of access made on an M-cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cycle {
+    Internal,
+    Read(u16, u8),
+    Write(u16, u8),
+}
+
+// 64KiB of flat memory, recording the access made on every M-cycle.
+struct TestBus {
+    mem: Vec<u8>,
+    cycles: Vec<Cycle>,
+}
+
+impl TestBus {
+    // Records an access on the current M-cycle. Accesses made before the first M-cycle, and
+    // after the first one of an M-cycle, are ignored.
+    fn record(&mut self, cycle: Cycle) {
+        if let Some(last @ Cycle::Internal) = self.cycles.last_mut() {
+            *last = cycle;
+        }
+    }
+}
+
+impl Bus for TestBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        let data = self.mem[addr as usize];
+        self.record(Cycle::Read(addr, data));
+        data
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.mem[addr as usize] = data;
+        self.record(Cycle::Write(addr, data));
+    }
+
+    fn tick(&mut self) {
+        self.cycles.push(Cycle::Internal);
+    }
+}
+
+fn num(state: &Value, key: &str) -> u16 {
+    state[key].as_u64().unwrap_or_else(|| panic!("missing `{}`", key)) as u16
+}
+
+fn setup(state: &Value) -> (Cpu, TestBus) {
+    let mut cpu = Cpu::default();
+    let reg = cpu.reg_mut();
+    reg.a = num(state, "a") as u8;
+    reg.f = num(state, "f") as u8;
+    reg.b = num(state, "b") as u8;
+    reg.c = num(state, "c") as u8;
+    reg.d = num(state, "d") as u8;
+    reg.e = num(state, "e") as u8;
+    reg.h = num(state, "h") as u8;
+    reg.l = num(state, "l") as u8;
+    reg.pc = num(state, "pc").wrapping_sub(1);
+    reg.sp = num(state, "sp");
+    cpu.set_ime(num(state, "ime") != 0);
+
+    let mut bus = TestBus { mem: vec![0; 0x10000],
+                            cycles: Vec::new() };
+    for (addr, data) in ram(state) {
+        bus.mem[addr as usize] = data;
+    }
+    (cpu, bus)
+}
+
+fn ram(state: &Value) -> Vec<(u16, u8)> {
+    state["ram"].as_array()
+                .into_iter()
+                .flatten()
+                .map(|entry| (entry[0].as_u64().unwrap() as u16, entry[1].as_u64().unwrap() as u8))
+                .collect()
+}
+
+// Parses an expected M-cycle, `[addr, data, "r-m"]`, `[addr, data, "-wm"]` or `null`.
+fn cycle(value: &Value) -> Cycle {
+    let addr = value[0].as_u64().unwrap_or_default() as u16;
+    let data = value[1].as_u64().unwrap_or_default() as u8;
+    match value[2].as_str() {
+        Some(kind) if kind.starts_with('r') => Cycle::Read(addr, data),
+        Some(kind) if kind.contains('w') => Cycle::Write(addr, data),
+        _ => Cycle::Internal,
+    }
+}
+
+// Runs a test. Returns the list of mismatches.
+fn run(test: &Value) -> Vec<String> {
+    let (mut cpu, mut bus) = setup(&test["initial"]);
+    cpu.step(&mut bus);
+    // Fetch of the next opcode.
+    bus.tick();
+    bus.read(cpu.reg().pc);
+    cpu.reg_mut().pc = cpu.reg().pc.wrapping_add(1);
+
+    let expected = &test["final"];
+    let mut errors = Vec::new();
+    let reg = cpu.reg();
+    let regs = [("a", u16::from(reg.a)),
+                ("f", u16::from(reg.f)),
+                ("b", u16::from(reg.b)),
+                ("c", u16::from(reg.c)),
+                ("d", u16::from(reg.d)),
+                ("e", u16::from(reg.e)),
+                ("h", u16::from(reg.h)),
+                ("l", u16::from(reg.l)),
+                ("pc", reg.pc),
+                ("sp", reg.sp),
+                ("ime", u16::from(cpu.ime()))];
+    for (name, actual) in regs {
+        if expected.get(name).is_some() && num(expected, name) != actual {
+            errors.push(format!("{}: expected {:04X}, got {:04X}", name, num(expected, name), actual));
+        }
+    }
+    for (addr, data) in ram(expected) {
+        let actual = bus.mem[addr as usize];
+        if actual != data {
+            errors.push(format!("[{:04X}]: expected {:02X}, got {:02X}", addr, data, actual));
+        }
+    }
+
+    let expected: Vec<Cycle> = test["cycles"].as_array().into_iter().flatten().map(cycle).collect();
+    // Skip the fetch of the opcode, which precedes the test.
+    let actual = &bus.cycles[1..];
+    if expected.len() != actual.len() {
+        errors.push(format!("expected {} M-cycles, got {}", expected.len(), actual.len()));
+    } else {
+        for (i, (expected, actual)) in expected.iter().zip(actual).enumerate() {
+            // The address bus isn't checked on internal cycles.
+            if *expected != Cycle::Internal && expected != actual {
+                errors.push(format!("M-cycle {}: expected {:?}, got {:?}", i, expected, actual));
+            }
+        }
+    }
+    errors
+}
+
+#[test]
+fn single_step_tests() {
+    let dir = env::var_os("SM83_TESTS").map_or_else(|| PathBuf::from("tests/sm83/v1"), PathBuf::from);
+    let Ok(entries) = fs::read_dir(&dir) else {
+        eprintln!("skipped: {} not found (set SM83_TESTS)", dir.display());
+        return;
+    };
+    let mut files: Vec<PathBuf> = entries.map(|e| e.unwrap().path())
+                                         .filter(|p| p.extension().is_some_and(|e| e == "json"))
+                                         .collect();
+    files.sort();
+
+    let (mut passed, mut failed) = (0, 0);
+    for path in files {
+        let text = fs::read_to_string(&path).unwrap();
+        let tests: Vec<Value> = serde_json::from_str(&text)
+            .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        let mut reported = 0;
+        for test in &tests {
+            let errors = run(test);
+            if errors.is_empty() {
+                passed += 1;
+                continue;
+            }
+            failed += 1;
+            if reported < MAX_REPORTED {
+                reported += 1;
+                eprintln!("{} `{}`:", path.display(), test["name"].as_str().unwrap_or_default());
+                for error in errors {
+                    eprintln!("    {}", error);
+                }
+            }
+        }
+    }
+    eprintln!("{} passed, {} failed", passed, failed);
+    assert_eq!(failed, 0);
+}
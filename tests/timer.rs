@@ -0,0 +1,195 @@
+//! Timer tests, after mooneye's `acceptance/timer` suite.
+mod common;
+
+use common::System;
+use emulator::Builder;
+
+const DIV: u16 = 0xff04;
+const TIMA: u16 = 0xff05;
+const TMA: u16 = 0xff06;
+const TAC: u16 = 0xff07;
+const IF: u16 = 0xff0f;
+const TIMER_INT: u8 = 0x4;
+
+// M-cycles between TIMA increments for every TAC clock select.
+const PERIODS: [u64; 4] = [256, 4, 16, 64];
+
+impl System {
+    // Starts with the system counter at 0 and the timer interrupt cleared.
+    fn new() -> Self {
+        let mut sys = Self::with(Builder::default().gb_mode());
+        sys.write(DIV, 0);
+        sys.write(IF, 0);
+        sys
+    }
+
+    fn timer_int(&mut self) -> bool {
+        self.read(IF) & TIMER_INT != 0
+    }
+}
+
+#[test]
+fn div_is_upper_byte_of_counter() {
+    let mut sys = System::new();
+    // DIV is incremented every 64 M-cycles (256 CPU cycles).
+    sys.idle(61);
+    assert_eq!(sys.read(DIV), 0);
+    assert_eq!(sys.read(DIV), 1);
+    sys.idle(63);
+    assert_eq!(sys.read(DIV), 2);
+}
+
+#[test]
+fn div_write_resets_counter() {
+    let mut sys = System::new();
+    sys.idle(1000);
+    sys.write(DIV, 0x5a);
+    assert_eq!(sys.read(DIV), 0);
+    sys.idle(61);
+    assert_eq!(sys.read(DIV), 0);
+    assert_eq!(sys.read(DIV), 1);
+}
+
+// tim00, tim01, tim10, tim11
+#[test]
+fn tima_increments_at_selected_rate() {
+    for (select, &period) in PERIODS.iter().enumerate() {
+        let mut sys = System::new();
+        sys.write(TIMA, 0);
+        sys.write(TAC, 0x4 | select as u8);
+        // The counter was reset 3 M-cycles ago, so the first falling edge is `period - 3`
+        // M-cycles away.
+        sys.idle(period - 4);
+        assert_eq!(sys.read(TIMA), 1, "TAC={}", select);
+        sys.idle(period - 2);
+        assert_eq!(sys.read(TIMA), 1, "TAC={}", select);
+        assert_eq!(sys.read(TIMA), 2, "TAC={}", select);
+    }
+}
+
+#[test]
+fn tima_stopped_when_disabled() {
+    let mut sys = System::new();
+    sys.write(TIMA, 0);
+    sys.write(TAC, 0x1);
+    sys.idle(100);
+    assert_eq!(sys.read(TIMA), 0);
+}
+
+// div_write: resetting the counter while the selected bit is set is a falling edge.
+#[test]
+fn div_write_increments_tima() {
+    for (select, &period) in PERIODS.iter().enumerate() {
+        let mut sys = System::new();
+        sys.write(TAC, 0x4 | select as u8);
+        sys.write(DIV, 0);
+        sys.write(TIMA, 0);
+        // The selected bit is set in the second half of the period.
+        sys.idle(period / 2 - 2);
+        sys.write(DIV, 0);
+        assert_eq!(sys.read(TIMA), 1, "TAC={}", select);
+
+        // Bits 5, 7 and 9 are still clear right after a reset: no edge.
+        if select != 1 {
+            let mut sys = System::new();
+            sys.write(TAC, 0x4 | select as u8);
+            sys.write(DIV, 0);
+            sys.write(TIMA, 0);
+            sys.write(DIV, 0);
+            assert_eq!(sys.read(TIMA), 0, "TAC={}", select);
+        }
+    }
+}
+
+// rapid_toggle: disabling the timer while the selected bit is set is a falling edge.
+#[test]
+fn tac_write_increments_tima() {
+    let mut sys = System::new();
+    sys.write(TAC, 0x5);
+    sys.write(DIV, 0);
+    sys.write(TIMA, 0);
+    // Bit 3 is set.
+    sys.idle(1);
+    sys.write(TAC, 0x1);
+    assert_eq!(sys.read(TIMA), 1);
+
+    // Switching from a set bit to a clear bit is an edge as well.
+    let mut sys = System::new();
+    sys.write(TAC, 0x4);
+    sys.write(DIV, 0);
+    sys.write(TIMA, 0);
+    // Bit 9 set, bit 3 clear.
+    sys.idle(126);
+    sys.write(TAC, 0x5);
+    assert_eq!(sys.read(TIMA), 1);
+}
+
+// tima_reload
+#[test]
+fn tima_overflow_reloads_one_cycle_later() {
+    let mut sys = System::new();
+    sys.write(TMA, 0x42);
+    sys.write(TIMA, 0xff);
+    sys.write(TAC, 0x5);
+    sys.write(DIV, 0);
+    // The increment happens on the 4th M-cycle after the reset.
+    sys.idle(3);
+    assert_eq!(sys.read(TIMA), 0x00);
+    assert_eq!(sys.read(TIMA), 0x42);
+    assert!(sys.timer_int());
+}
+
+// tima_reload: the interrupt is requested along with the reload.
+#[test]
+fn tima_overflow_interrupt_one_cycle_later() {
+    let mut sys = System::new();
+    sys.write(TMA, 0x42);
+    sys.write(TIMA, 0xff);
+    sys.write(TAC, 0x5);
+    sys.write(DIV, 0);
+    sys.idle(3);
+    assert!(!sys.timer_int());
+    assert!(sys.timer_int());
+}
+
+// tima_write_reloading: writing TIMA right after the overflow cancels the reload.
+#[test]
+fn tima_write_cancels_reload() {
+    let mut sys = System::new();
+    sys.write(TMA, 0x42);
+    sys.write(TIMA, 0xff);
+    sys.write(TAC, 0x5);
+    sys.write(DIV, 0);
+    sys.idle(3);
+    sys.write(TIMA, 0x10);
+    assert_eq!(sys.read(TIMA), 0x10);
+    assert!(!sys.timer_int());
+}
+
+// tima_write_reloading: writing TIMA while it is reloaded is ignored.
+#[test]
+fn tima_write_during_reload_is_ignored() {
+    let mut sys = System::new();
+    sys.write(TMA, 0x42);
+    sys.write(TIMA, 0xff);
+    sys.write(TAC, 0x5);
+    sys.write(DIV, 0);
+    sys.idle(4);
+    sys.write(TIMA, 0x10);
+    assert_eq!(sys.read(TIMA), 0x42);
+    assert!(sys.timer_int());
+}
+
+// tma_write_reloading: writing TMA while TIMA is reloaded goes to TIMA too.
+#[test]
+fn tma_write_during_reload_is_copied() {
+    let mut sys = System::new();
+    sys.write(TMA, 0x42);
+    sys.write(TIMA, 0xff);
+    sys.write(TAC, 0x5);
+    sys.write(DIV, 0);
+    sys.idle(4);
+    sys.write(TMA, 0x24);
+    assert_eq!(sys.read(TIMA), 0x24);
+    assert_eq!(sys.read(TMA), 0x24);
+}
@@ -0,0 +1,108 @@
+//! Instruction tracer tests.
+use emulator::{
+    cartridge::{self, cartridge::Cartridge},
+    cpu::cpu::trace::Tracer,
+    Builder, GameBoy,
+};
+use std::{cell::RefCell, io, io::Write, rc::Rc};
+
+// Trace output shared with the test.
+#[derive(Clone, Default)]
+struct Log(Rc<RefCell<Vec<u8>>>);
+
+impl Log {
+    fn lines(&self) -> Vec<String> {
+        String::from_utf8(self.0.borrow().clone()).unwrap().lines().map(str::to_string).collect()
+    }
+
+    fn pcs(&self) -> Vec<String> {
+        self.lines().iter().map(|line| line.split(' ').nth(9).unwrap().to_string()).collect()
+    }
+}
+
+impl Write for Log {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// MBC1 ROM that calls a function in bank 2, after the boot ROM.
+fn system() -> GameBoy<Box<dyn Cartridge>, (), ()> {
+    let mut rom = vec![0; 0x10000];
+    rom[0x147] = 0x01;
+    rom[0x148] = 0x01;
+    #[rustfmt::skip]
+    let code = [
+        0x3e, 0x02,       // $0100: LD A,$02
+        0xea, 0x00, 0x20, // $0102: LD ($2000),A
+        0xcd, 0x00, 0x40, // $0105: CALL $4000
+        0x18, 0xfe,       // $0108: JR $0108
+    ];
+    rom[0x100..0x100 + code.len()].copy_from_slice(&code);
+    // every bank returns at once, except bank 2
+    for bank in rom.chunks_mut(0x4000).skip(1) {
+        bank[0] = 0xc9;
+    }
+    rom[0x8000..0x8002].copy_from_slice(&[0x00, 0xc9]);
+    let cartridge = cartridge::from_bytes(&rom).unwrap();
+    Builder::default().cartridge(cartridge).gb_mode().skip_boot().build()
+}
+
+fn run(tracer: Tracer, steps: usize) -> GameBoy<Box<dyn Cartridge>, (), ()> {
+    let mut gb = system();
+    gb.cpu_mut().set_tracer(Some(tracer));
+    for _ in 0..steps {
+        gb.step();
+    }
+    gb
+}
+
+#[test]
+fn gameboy_doctor_format() {
+    let log = Log::default();
+    run(Tracer::new(log.clone()), 7);
+    let lines = log.lines();
+    assert_eq!(lines[0], "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:3E,02,EA,00");
+    assert_eq!(lines[1], "A:02 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0102 PCMEM:EA,00,20,CD");
+    assert_eq!(lines[3], "A:02 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFC PC:4000 PCMEM:00,C9,00,00");
+    assert_eq!(log.pcs(),
+               ["PC:0100", "PC:0102", "PC:0105", "PC:4000", "PC:4001", "PC:0108", "PC:0108"]);
+}
+
+#[test]
+fn filters() {
+    let log = Log::default();
+    run(Tracer::new(log.clone()).pc_range(0x0101..=0x0105), 7);
+    assert_eq!(log.pcs(), ["PC:0102", "PC:0105"]);
+
+    let log = Log::default();
+    run(Tracer::new(log.clone()).bank(2), 7);
+    assert_eq!(log.pcs(), ["PC:4000", "PC:4001"]);
+
+    let log = Log::default();
+    run(Tracer::new(log.clone()).bank(0), 7);
+    assert_eq!(log.pcs(), ["PC:0100", "PC:0102", "PC:0105", "PC:0108", "PC:0108"]);
+
+    let log = Log::default();
+    run(Tracer::new(log.clone()).bank(2).pc_range(0x4001..=0x4001), 7);
+    assert_eq!(log.pcs(), ["PC:4001"]);
+}
+
+#[test]
+fn start_stop() {
+    let log = Log::default();
+    let mut gb = run(Tracer::new(log.clone()), 2);
+    gb.cpu_mut().tracer_mut().unwrap().stop();
+    assert!(!gb.cpu().tracer().unwrap().is_enabled());
+    for _ in 0..3 {
+        gb.step();
+    }
+    gb.cpu_mut().tracer_mut().unwrap().start();
+    gb.step();
+    assert_eq!(log.pcs(), ["PC:0100", "PC:0102", "PC:0108"]);
+}